@@ -0,0 +1,31 @@
+extern crate alloc;
+
+use core::fmt::Display;
+
+use crate::lambda::LambdaTree;
+use crate::typing::TypeExpr;
+
+
+/// A property of terms that is checked by an `@assert` directive
+#[derive(Debug, Clone)]
+pub enum Assertion {
+    /// Both terms have the same normal form
+    Equal(LambdaTree, LambdaTree),
+    /// Both terms are the same up to the names of bound variables
+    AlphaEqual(LambdaTree, LambdaTree),
+    /// The type inferred for the term is the given one
+    Type(LambdaTree, TypeExpr),
+    /// The term has no normal form within the fuel limit
+    Diverges(LambdaTree),
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Assertion::Equal(left, right) => write!(f, "@assert {} == {}", left, right),
+            Assertion::AlphaEqual(left, right) => write!(f, "@assert_alpha {} == {}", left, right),
+            Assertion::Type(term, ty) => write!(f, "@assert_type {} : {}", term, ty),
+            Assertion::Diverges(term) => write!(f, "@assert_diverges {}", term),
+        }
+    }
+}
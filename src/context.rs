@@ -1,16 +1,51 @@
 extern crate alloc;
 
-use alloc::collections::BTreeMap;
-use alloc::rc::Rc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
 
-use crate::lambda::NamedTerm;
+use crate::data::DataType;
+use crate::dependencies::DependencyGraph;
+use crate::lambda::{LambdaTree, NamedTerm, Shared};
+use crate::parsing::OperatorTable;
+use crate::settings::Value;
 
 
-pub type ContextFrame = Vec<Rc<NamedTerm>>;
+/// What started a scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// An `@begin` block, which keeps its definitions to itself
+    Block,
+    /// An included file, whose definitions are kept
+    File,
+    /// An imported file, whose definitions are only bound by the import
+    Module,
+}
+
+/// Definitions that are restored when a block ends
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    pub data_types: BTreeMap<String, (Shared<DataType>, Shared<NamedTerm>)>,
+    pub definitions: BTreeMap<String, LambdaTree>,
+    pub dependency_graph: DependencyGraph,
+    pub included: BTreeSet<String>,
+    pub named_terms: BTreeMap<String, Shared<NamedTerm>>,
+    pub operators: OperatorTable,
+}
+
+/// State from before a scope started
+pub struct ContextFrame {
+    pub kind: FrameKind,
+    /// Values of all settings
+    pub settings: Vec<(&'static str, Value)>,
+    /// Only saved for scopes that do not keep their definitions
+    pub namespace: Option<Namespace>,
+}
 
 
+/// The scopes that are currently open, innermost last
+#[derive(Default)]
 pub struct ContextTracker {
-    current_terms: BTreeMap<String, Rc<NamedTerm>>,
     frames: Vec<ContextFrame>,
 }
 
@@ -18,31 +53,24 @@ pub struct ContextTracker {
 impl ContextTracker {
     pub fn new() -> Self {
         ContextTracker {
-            current_terms: BTreeMap::new(),
             frames: Vec::new(),
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<Rc<NamedTerm>> {
-        self.current_terms.get(name).cloned()
+    /// The innermost scope
+    pub fn top(&self) -> Option<&ContextFrame> {
+        self.frames.last()
     }
 
-    pub fn push_frame(&mut self, frame: ContextFrame) {
-        self.frames.push(frame);
-        self.rebuild_current_terms();
+    pub fn depth(&self) -> usize {
+        self.frames.len()
     }
 
-    pub fn pop_frame(&mut self) {
-        self.frames.pop();
-        self.rebuild_current_terms();
+    pub fn push_frame(&mut self, frame: ContextFrame) {
+        self.frames.push(frame);
     }
 
-    fn rebuild_current_terms(&mut self) {
-        self.current_terms = BTreeMap::new();
-        for frame in &self.frames {
-            for named in frame {
-                self.current_terms.insert(named.name().to_owned(), named.clone());
-            }
-        }
+    pub fn pop_frame(&mut self) -> Option<ContextFrame> {
+        self.frames.pop()
     }
 }
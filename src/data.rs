@@ -0,0 +1,162 @@
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::str::FromStr;
+
+use crate::lambda::LambdaTree;
+
+
+/// Encoding used for the terms generated from a [`DataType`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Values are their own fold, recursive fields are passed to the handlers already folded
+    Church,
+    /// Values pass their fields to the handlers unchanged
+    #[default]
+    Scott,
+}
+
+/// Declaration of an algebraic data type like `Maybe = Nothing | Just x`
+#[derive(Debug, Clone)]
+pub struct DataType {
+    name: String,
+    constructors: Vec<Constructor>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Constructor {
+    name: String,
+    fields: Vec<String>,
+}
+
+impl DataType {
+    pub fn new(name: String, constructors: Vec<Constructor>) -> Self {
+        DataType { name, constructors }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn constructors(&self) -> &[Constructor] {
+        &self.constructors
+    }
+
+    /// Name of the eliminator, which applies a value to one handler per constructor
+    pub fn eliminator_name(&self) -> String {
+        format!("match{}", self.name)
+    }
+
+    /// Generate the named terms for the constructors and the eliminator
+    ///
+    /// The handlers are bound to the names of the constructors, so normal forms of values read
+    /// like `\Nothing . \Just . Just x`.
+    pub fn definitions(&self, encoding: Encoding) -> Vec<(String, LambdaTree)> {
+        let var = |name: &str| LambdaTree::new_variable(name.to_owned());
+        let abstract_over = |vars: &[String], body| vars.iter().rev()
+            .fold(body, |body, v| LambdaTree::new_abstraction(v.clone(), body));
+        let apply_to = |head, args: &[String]| args.iter()
+            .fold(head, |term, arg| LambdaTree::new_application(term, var(arg)));
+
+        let mut definitions = Vec::new();
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            let fields = constructor.parameters();
+            let handlers: Vec<String> = self.constructors.iter()
+                .map(|c| fresh(&c.name, &fields))
+                .collect();
+            let mut body = var(&handlers[i]);
+            for (field, parameter) in constructor.fields.iter().zip(&fields) {
+                let argument = if encoding == Encoding::Church && field == &self.name {
+                    apply_to(var(parameter), &handlers)
+                } else {
+                    var(parameter)
+                };
+                body = LambdaTree::new_application(body, argument);
+            }
+            let term = abstract_over(&fields, abstract_over(&handlers, body));
+            definitions.push((constructor.name.clone(), term));
+        }
+
+        let handlers: Vec<String> = self.constructors.iter().map(|c| c.name.clone()).collect();
+        let value = fresh("t", &handlers);
+        let eliminator = LambdaTree::new_abstraction(value.clone(),
+            abstract_over(&handlers, apply_to(var(&value), &handlers)));
+        definitions.push((self.eliminator_name(), eliminator));
+        definitions
+    }
+}
+
+impl Constructor {
+    /// Fields are named by the user, fields named like the data type itself are recursive
+    pub fn new(name: String, fields: Vec<String>) -> Self {
+        Constructor { name, fields }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Distinct parameter names for the fields
+    fn parameters(&self) -> Vec<String> {
+        let mut parameters = Vec::new();
+        for field in &self.fields {
+            let parameter = fresh(field, &parameters);
+            parameters.push(parameter);
+        }
+        parameters
+    }
+}
+
+/// Add primes to `name` until it differs from all names in `taken`
+fn fresh(name: &str, taken: &[String]) -> String {
+    let mut name = name.to_owned();
+    while taken.contains(&name) {
+        name = format!("{}'", name);
+    }
+    name
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "church" => Ok(Encoding::Church),
+            "scott" => Ok(Encoding::Scott),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Encoding::Church => write!(f, "church"),
+            Encoding::Scott => write!(f, "scott"),
+        }
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} =", self.name)?;
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            if i > 0 {
+                write!(f, " |")?;
+            }
+            write!(f, " {}", constructor.name)?;
+            for field in &constructor.fields {
+                write!(f, " {}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,15 +3,16 @@ extern crate alloc;
 use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
-use alloc::format;
 use alloc::string::String;
-use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::fmt::Display;
+use core::mem;
 
 use crate::lambda::{LambdaNode, LambdaTree};
 
 
-#[derive(Debug,Clone,Eq,PartialEq)]
+#[derive(Debug,Clone,Eq)]
 pub enum DeBruijnNode {
     BoundVariable(usize),
     FreeVariable(String),
@@ -19,67 +20,153 @@ pub enum DeBruijnNode {
     Application(Box<DeBruijnNode>, Box<DeBruijnNode>),
 }
 
-fn to_debrujin_helper(l: LambdaTree, map: &mut BTreeMap<String, usize>, depth: usize) -> DeBruijnNode {
-    match l.node() {
-        LambdaNode::Variable(name) => {
-            if map.contains_key(name.as_str()) {
-                DeBruijnNode::BoundVariable(depth - *map.get(name.as_str()).unwrap())
-            } else {
-                DeBruijnNode::FreeVariable(name.clone())
-            }
-        },
-        LambdaNode::Abstraction(var_name, inner) => {
-            map.insert(var_name.to_owned(), depth);
-            let new = DeBruijnNode::Abstraction(Box::new(to_debrujin_helper(inner.clone(), map, depth + 1)));
-            map.remove(var_name.as_str());
-            new
-        },
-        LambdaNode::Application(left, right) => {
-            DeBruijnNode::Application(Box::new(to_debrujin_helper(left.clone(), map, depth)),
-                Box::new(to_debrujin_helper(right.clone(), map, depth)))
-        },
-        LambdaNode::Named(named_term) => {
-            let term = named_term.term();
-            to_debrujin_helper(term.clone(), map, depth)
-        },
-        LambdaNode::ChurchNum(n) => to_debrujin_helper(LambdaTree::unwrap_church_num(*n), map, depth),
-        LambdaNode::Macro(_, _) => unreachable!(),
+fn to_debrujin_helper(l: LambdaTree) -> DeBruijnNode {
+    enum Work {
+        Visit(LambdaTree, usize),
+        Abstraction(String),
+        Application,
+    }
+
+    // binding depths of all variables in scope, innermost last
+    let mut map: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut work = vec![Work::Visit(l, 0)];
+    let mut results = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(l, depth) => match l.node() {
+                LambdaNode::Variable(name) => {
+                    match map.get(name.as_str()).and_then(|depths| depths.last()) {
+                        Some(bound_at) => results.push(DeBruijnNode::BoundVariable(depth - bound_at)),
+                        None => results.push(DeBruijnNode::FreeVariable(name.clone())),
+                    }
+                },
+                LambdaNode::Abstraction(var_name, inner) => {
+                    map.entry(var_name.to_owned()).or_default().push(depth);
+                    work.push(Work::Abstraction(var_name.to_owned()));
+                    work.push(Work::Visit(inner.clone(), depth + 1));
+                },
+                LambdaNode::Application(left, right) => {
+                    work.push(Work::Application);
+                    work.push(Work::Visit(right.clone(), depth));
+                    work.push(Work::Visit(left.clone(), depth));
+                },
+                // same as the redex (\var . body) value
+                LambdaNode::Let(var_name, value, body) => {
+                    map.entry(var_name.to_owned()).or_default().push(depth);
+                    work.push(Work::Application);
+                    work.push(Work::Visit(value.clone(), depth));
+                    work.push(Work::Abstraction(var_name.to_owned()));
+                    work.push(Work::Visit(body.clone(), depth + 1));
+                },
+                LambdaNode::Named(named_term) => work.push(Work::Visit(named_term.term(), depth)),
+                LambdaNode::ChurchNum(n) => work.push(Work::Visit(LambdaTree::unwrap_church_num(*n), depth)),
+                LambdaNode::Macro(_, _) | LambdaNode::Match(_, _) => unreachable!(),
+            },
+            Work::Abstraction(var_name) => {
+                if let Some(depths) = map.get_mut(var_name.as_str()) {
+                    depths.pop();
+                    if depths.is_empty() {
+                        map.remove(var_name.as_str());
+                    }
+                }
+                let inner = results.pop().unwrap();
+                results.push(DeBruijnNode::Abstraction(Box::new(inner)));
+            },
+            Work::Application => {
+                let right = results.pop().unwrap();
+                let left = results.pop().unwrap();
+                results.push(DeBruijnNode::Application(Box::new(left), Box::new(right)));
+            },
+        }
     }
+    results.pop().unwrap()
+}
 
+impl DeBruijnNode {
+    /// Move the children out of this node, so they can be dropped one by one
+    fn take_children(&mut self, stack: &mut Vec<DeBruijnNode>) {
+        use DeBruijnNode::*;
+        match self {
+            Abstraction(term) => stack.push(mem::replace(term.as_mut(), BoundVariable(0))),
+            Application(term1, term2) => {
+                stack.push(mem::replace(term1.as_mut(), BoundVariable(0)));
+                stack.push(mem::replace(term2.as_mut(), BoundVariable(0)));
+            },
+            BoundVariable(_) | FreeVariable(_) => (),
+        }
+    }
 }
 
 impl From<LambdaTree> for DeBruijnNode {
     fn from(value: LambdaTree) -> Self {
-        to_debrujin_helper(value, &mut BTreeMap::new(), 0)
+        to_debrujin_helper(value)
+    }
+}
+
+impl PartialEq for DeBruijnNode {
+    fn eq(&self, other: &Self) -> bool {
+        use DeBruijnNode::*;
+        let mut stack = vec![(self, other)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (BoundVariable(i), BoundVariable(j)) => if i != j { return false },
+                (FreeVariable(a), FreeVariable(b)) => if a != b { return false },
+                (Abstraction(a), Abstraction(b)) => stack.push((a, b)),
+                (Application(a1, a2), Application(b1, b2)) => {
+                    stack.push((a2, b2));
+                    stack.push((a1, b1));
+                },
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Drop for DeBruijnNode {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.take_children(&mut stack);
+        }
     }
 }
 
 impl Display for DeBruijnNode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use DeBruijnNode::*;
-        match self {
-            FreeVariable(name) => write!(f, "{}", name),
-            BoundVariable(i) => write!(f, "{}", i),
-            Abstraction(term) => write!(f, "\\ {}", term),
-            Application(term1, term2) => {
-                let s1 = if let DeBruijnNode::FreeVariable(name) = &**term1 {
-                    name.to_string()
-                } else if let DeBruijnNode::BoundVariable(i) = &**term1 {
-                    format!("{}", i)
-                } else if let DeBruijnNode::Application(_, _) = &**term1 {
-                    term1.to_string()
-                } else {
-                    format!("({})", term1)
-                };
-                let s2 = if let DeBruijnNode::FreeVariable(name) = &**term2 {
-                    name.to_string()
-                } else if let DeBruijnNode::BoundVariable(i) = &**term2 {
-                    format!("{}", i)
-                } else {
-                    format!("({})", term2)
-                };
-                write!(f, "{} {}", s1, s2)
-            },
+
+        enum Piece<'a> {
+            Node(&'a DeBruijnNode),
+            Text(&'static str),
+        }
+
+        let mut pieces = vec![Piece::Node(self)];
+        while let Some(piece) = pieces.pop() {
+            let node = match piece {
+                Piece::Node(node) => node,
+                Piece::Text(text) => { f.write_str(text)?; continue },
+            };
+            match node {
+                FreeVariable(name) => write!(f, "{}", name)?,
+                BoundVariable(i) => write!(f, "{}", i)?,
+                Abstraction(term) => {
+                    f.write_str("\\ ")?;
+                    pieces.push(Piece::Node(term));
+                },
+                Application(term1, term2) => {
+                    let parens1 = !matches!(**term1, FreeVariable(_) | BoundVariable(_) | Application(..));
+                    let parens2 = !matches!(**term2, FreeVariable(_) | BoundVariable(_));
+                    if parens2 { pieces.push(Piece::Text(")")); }
+                    pieces.push(Piece::Node(term2));
+                    pieces.push(Piece::Text(if parens2 { " (" } else { " " }));
+                    if parens1 { pieces.push(Piece::Text(")")); }
+                    pieces.push(Piece::Node(term1));
+                    if parens1 { pieces.push(Piece::Text("(")); }
+                },
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,189 @@
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Write};
+use core::str::FromStr;
+
+
+/// When the names used in a definition are looked up, set with `@set binding`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Names refer to the definitions they had when the term was defined
+    #[default]
+    Early,
+    /// Names refer to their latest definitions, so they may also be defined later on
+    Late,
+}
+
+/// The names each named term refers to
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        DependencyGraph { dependencies: BTreeMap::new() }
+    }
+
+    /// Replace the dependencies of `name`
+    pub fn set(&mut self, name: String, dependencies: BTreeSet<String>) {
+        self.dependencies.insert(name, dependencies);
+    }
+
+    /// Forget the dependencies of `name`, the names that refer to it keep their edges
+    pub fn remove(&mut self, name: &str) {
+        self.dependencies.remove(name);
+    }
+
+    /// Names that `name` refers to directly
+    pub fn dependencies(&self, name: &str) -> Option<&BTreeSet<String>> {
+        self.dependencies.get(name)
+    }
+
+    /// Names that refer to one of `names`, directly or through other named terms
+    pub fn dependents<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> BTreeSet<String> {
+        let mut dependents = BTreeSet::new();
+        let mut stack: Vec<&str> = names.into_iter().collect();
+        while let Some(name) = stack.pop() {
+            for (dependent, dependencies) in &self.dependencies {
+                if dependencies.contains(name) && dependents.insert(dependent.clone()) {
+                    stack.push(dependent);
+                }
+            }
+        }
+        dependents
+    }
+
+    /// Names that no other named term refers to
+    pub fn unused(&self) -> BTreeSet<String> {
+        let used: BTreeSet<&String> = self.dependencies.iter()
+            .flat_map(|(name, dependencies)| dependencies.iter().filter(move |d| *d != name))
+            .collect();
+        self.dependencies.keys()
+            .filter(|name| !used.contains(name))
+            .cloned()
+            .collect()
+    }
+
+    /// Split `names` into groups that refer to each other in a cycle
+    ///
+    /// Only the dependencies between `names` are considered. A group comes after all groups it
+    /// depends on, so the groups can be defined in the returned order.
+    pub fn components(&self, names: &BTreeSet<String>) -> Vec<Vec<String>> {
+        // Tarjan's algorithm, with an explicit stack of the nodes and their remaining edges
+        let mut index: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut low_link: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut on_stack: BTreeSet<&str> = BTreeSet::new();
+        let mut stack: Vec<&str> = Vec::new();
+        let mut components = Vec::new();
+
+        let edges = |name: &str| -> Vec<&str> {
+            self.dependencies.get(name)
+                .map(|d| d.iter().filter_map(|n| names.get(n)).map(String::as_str).collect())
+                .unwrap_or_default()
+        };
+
+        for root in names {
+            if index.contains_key(root.as_str()) {
+                continue;
+            }
+            let mut work = vec![(root.as_str(), edges(root))];
+            index.insert(root, index.len());
+            low_link.insert(root, index[root.as_str()]);
+            stack.push(root);
+            on_stack.insert(root);
+
+            while let Some((node, remaining)) = work.last_mut() {
+                let node = *node;
+                if let Some(next) = remaining.pop() {
+                    if !index.contains_key(next) {
+                        index.insert(next, index.len());
+                        low_link.insert(next, index[next]);
+                        stack.push(next);
+                        on_stack.insert(next);
+                        work.push((next, edges(next)));
+                    } else if on_stack.contains(next) {
+                        let low = low_link[node].min(index[next]);
+                        low_link.insert(node, low);
+                    }
+                    continue;
+                }
+
+                work.pop();
+                if let Some((parent, _)) = work.last() {
+                    let low = low_link[*parent].min(low_link[node]);
+                    low_link.insert(parent, low);
+                }
+                if low_link[node] == index[node] {
+                    let mut component = Vec::new();
+                    loop {
+                        let member = stack.pop().unwrap();
+                        on_stack.remove(member);
+                        component.push(member.into());
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.reverse();
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+}
+
+/// Print a list of names after `label`, or `empty` if there are none
+pub fn write_names(out: &mut impl Write, label: &str, empty: &str, names: &BTreeSet<String>) -> core::fmt::Result {
+    if names.is_empty() {
+        writeln!(out, "{}", empty)
+    } else {
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        writeln!(out, "{}: {}", label, names.join(", "))
+    }
+}
+
+impl FromStr for Binding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "early" => Ok(Binding::Early),
+            "late" => Ok(Binding::Late),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Binding::Early => write!(f, "early"),
+            Binding::Late => write!(f, "late"),
+        }
+    }
+}
+
+/// The graph in the DOT format of Graphviz, unused named terms are drawn dashed
+impl Display for DependencyGraph {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let unused = self.unused();
+        writeln!(f, "digraph dependencies {{")?;
+        for (name, dependencies) in &self.dependencies {
+            if unused.contains(name) {
+                writeln!(f, "    \"{}\" [style=dashed];", name)?;
+            } else {
+                writeln!(f, "    \"{}\";", name)?;
+            }
+            // late bound terms may refer to names that are not defined yet
+            for dependency in dependencies.iter().filter(|d| self.dependencies.contains_key(*d)) {
+                writeln!(f, "    \"{}\" -> \"{}\";", name, dependency)?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::environment::{CaptureEnvironment, Environment};
+use crate::interpreter::Interpreter;
+use crate::testing::{self, TestResult};
+
+
+/// An interactive session shown in a code block of a Markdown file
+#[derive(Debug)]
+pub struct Transcript {
+    /// Line of the code block in the Markdown file
+    pub line: usize,
+    /// The lines entered at the prompt
+    pub inputs: Vec<String>,
+    /// Contents of the code block, the inputs with the output shown for them
+    pub text: String,
+}
+
+/// Find the code blocks in `markdown` that contain lines starting with `prompt`
+pub fn extract(markdown: &str, prompt: &str) -> Vec<Transcript> {
+    let mut transcripts = Vec::new();
+    let mut block: Option<(usize, Vec<&str>)> = None;
+    for (index, line) in markdown.lines().enumerate() {
+        if !line.trim_start().starts_with("```") {
+            if let Some((_, lines)) = &mut block {
+                lines.push(line);
+            }
+            continue;
+        }
+        match block.take() {
+            Some((line, lines)) => {
+                let inputs: Vec<String> = lines.iter()
+                    .filter_map(|l| l.strip_prefix(prompt))
+                    .map(str::to_owned)
+                    .collect();
+                if !inputs.is_empty() {
+                    transcripts.push(Transcript { line, inputs, text: lines.join("\n") });
+                }
+            },
+            None => block = Some((index + 1, Vec::new())),
+        }
+    }
+    transcripts
+}
+
+/// Enter the inputs one after another and return the session as the interactive mode shows it,
+/// with the prompt of the interpreter
+pub fn replay<E: Environment>(interpreter: &mut Interpreter<CaptureEnvironment<E>>, inputs: &[String]) -> String {
+    let mut session = String::new();
+    for input in inputs {
+        session.push_str(interpreter.prompt());
+        let result = interpreter.interpret_line(input);
+        session.push_str(input);
+        session.push('\n');
+        session.push_str(&interpreter.env().take_output());
+        session.push_str(&interpreter.format_result(&result));
+        session.push('\n');
+    }
+    session
+}
+
+/// Replay each transcript of a Markdown file in an interpreter of its own
+///
+/// A transcript passes if replaying it shows exactly the contents of its code block.
+/// The input lines are recognized by the `prompt` setting of the interpreters.
+pub fn run<E: Environment>(file: &Path, mut new_interpreter: impl FnMut() -> Interpreter<CaptureEnvironment<E>>)
+        -> io::Result<Vec<TestResult>> {
+    let markdown = std::fs::read_to_string(file)?;
+    let prompt = new_interpreter().prompt().to_owned();
+    let results = extract(&markdown, &prompt).into_iter()
+        .map(|transcript| {
+            let mut interpreter = new_interpreter();
+            let start = Instant::now();
+            let session = replay(&mut interpreter, &transcript.inputs);
+            let error = testing::check_output(&session, &transcript.text).err();
+            TestResult { name: format!("{}:{}", file.display(), transcript.line), duration: start.elapsed(), error }
+        })
+        .collect();
+    Ok(results)
+}
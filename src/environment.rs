@@ -1,26 +1,77 @@
 extern crate alloc;
 
+use alloc::borrow::ToOwned;
 use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::sync::Arc;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use core::time::Duration;
 
 use crate::error::*;
 
 pub trait Environment: {
-    type Instant;
+    type Instant: Copy;
 
     fn stdout(&mut self) -> &mut impl Write;
-    #[allow(dead_code)]
     fn stderr(&mut self) -> &mut impl Write;
     fn load(&self, file: &str) -> LashResult<String>;
     fn now(&self) -> Self::Instant;
     fn elapsed(&self, then: Self::Instant) -> Duration;
+
+    /// Check whether the user asked to cancel the running computation
+    ///
+    /// The request stays until the next computation starts.
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+
+    /// Start a computation that checks [`interrupted`](Self::interrupted) regularly
+    ///
+    /// Requests from before are dropped, unless another computation is still running.
+    fn begin_interruptible(&mut self) {}
+
+    /// End a computation started with [`begin_interruptible`](Self::begin_interruptible)
+    fn end_interruptible(&mut self) {}
+
+    /// The requests to cancel, to pass them on to computations on other threads
+    #[cfg(feature = "sync")]
+    fn interrupts(&self) -> Interrupts {
+        Interrupts::new()
+    }
+
+    /// Directories in which included files are looked up after the directory of the including file
+    fn search_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// A path that is the same for all paths to `file`
+    fn canonical_path(&self, file: &str) -> String {
+        file.to_owned()
+    }
+}
+
+/// Requests to cancel computations, which can be shared between threads
+#[derive(Debug, Clone, Default)]
+pub struct Interrupts {
+    requested: Arc<AtomicBool>,
+    /// Number of computations that currently check for requests
+    running: Arc<AtomicUsize>,
+}
+
+/// Wraps another environment and records everything that is printed instead of showing it
+///
+/// Standard output and standard error end up in the same transcript, so their order is kept.
+pub struct CaptureEnvironment<E: Environment> {
+    inner: E,
+    output: String,
 }
 
 #[cfg(feature = "std")]
 pub struct StdEnvironment {
     stdout: StdStdout,
     stderr: StdStderr,
+    interrupts: Interrupts,
 }
 
 #[cfg(feature = "std")]
@@ -39,11 +90,28 @@ impl Default for StdEnvironment {
 #[cfg(feature = "std")]
 impl StdEnvironment {
     pub fn new() -> Self {
+        Self::with_interrupts(Interrupts::new())
+    }
+
+    /// An environment that receives the given requests to cancel
+    pub fn with_interrupts(interrupts: Interrupts) -> Self {
         StdEnvironment {
             stdout: StdStdout {},
             stderr: StdStderr {},
+            interrupts,
         }
     }
+
+    /// Cancel running computations on Ctrl-C instead of terminating the process
+    ///
+    /// Outside of computations that check for requests Ctrl-C still terminates the process.
+    pub fn catch_interrupts(&self) -> LashResult<()> {
+        let interrupts = self.interrupts.clone();
+        ctrlc::set_handler(move || if !interrupts.request() {
+            std::process::exit(130);
+        })
+            .map_err(|e| LashError::new_interrupt_handler_error(e.to_string()))
+    }
 }
 
 #[cfg(feature = "std")]
@@ -70,6 +138,128 @@ impl Environment for StdEnvironment {
     fn elapsed(&self, then: Self::Instant) -> Duration {
         then.elapsed()
     }
+
+    fn interrupted(&mut self) -> bool {
+        self.interrupts.requested()
+    }
+
+    fn begin_interruptible(&mut self) {
+        self.interrupts.begin();
+    }
+
+    fn end_interruptible(&mut self) {
+        self.interrupts.end();
+    }
+
+    #[cfg(feature = "sync")]
+    fn interrupts(&self) -> Interrupts {
+        self.interrupts.clone()
+    }
+
+    fn search_path(&self) -> Vec<String> {
+        std::env::var_os("LASH_PATH")
+            .map(|paths| std::env::split_paths(&paths).map(|p| p.to_string_lossy().into_owned()).collect())
+            .unwrap_or_default()
+    }
+
+    fn canonical_path(&self, file: &str) -> String {
+        std::fs::canonicalize(file)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| file.to_owned())
+    }
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the running computations to stop, returns `false` if none is running
+    pub fn request(&self) -> bool {
+        if self.running.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        self.requested.store(true, Ordering::SeqCst);
+        true
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Start a computation, which drops older requests if no other computation is running
+    pub fn begin(&self) {
+        if self.running.fetch_add(1, Ordering::SeqCst) == 0 {
+            self.requested.store(false, Ordering::SeqCst);
+        }
+    }
+
+    pub fn end(&self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<E: Environment> CaptureEnvironment<E> {
+    pub fn new(inner: E) -> Self {
+        CaptureEnvironment {
+            inner,
+            output: String::new(),
+        }
+    }
+
+    /// Return the output printed so far and start a new transcript
+    pub fn take_output(&mut self) -> String {
+        core::mem::take(&mut self.output)
+    }
+}
+
+impl<E: Environment> Environment for CaptureEnvironment<E> {
+    type Instant = E::Instant;
+
+    fn stdout(&mut self) -> &mut impl Write {
+        &mut self.output
+    }
+
+    fn stderr(&mut self) -> &mut impl Write {
+        &mut self.output
+    }
+
+    fn load(&self, file: &str) -> LashResult<String> {
+        self.inner.load(file)
+    }
+
+    fn now(&self) -> Self::Instant {
+        self.inner.now()
+    }
+
+    fn elapsed(&self, then: Self::Instant) -> Duration {
+        self.inner.elapsed(then)
+    }
+
+    fn interrupted(&mut self) -> bool {
+        self.inner.interrupted()
+    }
+
+    fn begin_interruptible(&mut self) {
+        self.inner.begin_interruptible()
+    }
+
+    fn end_interruptible(&mut self) {
+        self.inner.end_interruptible()
+    }
+
+    #[cfg(feature = "sync")]
+    fn interrupts(&self) -> Interrupts {
+        self.inner.interrupts()
+    }
+
+    fn search_path(&self) -> Vec<String> {
+        self.inner.search_path()
+    }
+
+    fn canonical_path(&self, file: &str) -> String {
+        self.inner.canonical_path(file)
+    }
 }
 
 #[cfg(feature = "std")]
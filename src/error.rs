@@ -1,10 +1,15 @@
 extern crate alloc;
 
 use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use pest::error::LineColLocation;
 use pest::RuleType;
 use core::fmt::Display;
+use core::str::FromStr;
+use core::time::Duration;
 
 use crate::r#macro::Macro;
 
@@ -14,19 +19,68 @@ pub type LashResult<T> = Result<T, LashError>;
 pub struct LashError {
     error_type: LashErrorType,
     message: String,
+    context: Box<ErrorContext>,
+}
+
+/// Where an error occurred and how it can be fixed
+#[derive(Debug, Clone, Default)]
+struct ErrorContext {
+    file: Option<String>,
+    span: Option<Span>,
+    /// Line of the source code the span points into
+    source: Option<String>,
+    /// Hints on how to fix the error
+    help: Vec<String>,
+    /// Errors collected by [`LashError::new_multiple`]
+    errors: Vec<LashError>,
+    /// Whether a warning is reported as an error
+    denied: bool,
+}
+
+/// How warnings are reported, set with `@set warnings`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WarningLevel {
+    /// Warnings make the statement fail
+    Error,
+    /// Warnings are printed, but the statement is executed
+    #[default]
+    Warn,
+    /// Warnings are ignored
+    Off,
+}
+
+/// Position in the source code, lines and columns start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
 }
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug,Clone)]
 pub enum LashErrorType {
+    AssertionError,
     ChurchNumError,
+    DataError,
     FileError,
     FormatError,
+    FreeVariableWarning,
+    FuelError,
+    ImportError,
+    InterruptError,
     MacroArgError,
+    MultipleErrors,
+    OutputMismatchError,
+    RedefinitionWarning,
+    ScopeError,
     SetKeyError,
     SetValueError,
+    ShadowingWarning,
+    SizeError,
     SyntaxError,
+    TimeoutError,
     UnknownMacroError,
+    UnknownNameError,
     #[cfg(not(feature = "std"))]
     NotFoundError,
     #[cfg(not(feature = "std"))]
@@ -34,10 +88,46 @@ pub enum LashErrorType {
 }
 
 impl LashError {
+    fn new(error_type: LashErrorType, message: String) -> Self {
+        LashError { error_type, message, context: Box::default() }
+    }
+
+    /// Combine the errors of several statements, a single error is returned as it is
+    pub fn new_multiple(errors: Vec<LashError>) -> Self {
+        let mut flattened = Vec::new();
+        for error in errors {
+            match error.error_type {
+                LashErrorType::MultipleErrors => flattened.extend(error.context.errors),
+                _ => flattened.push(error),
+            }
+        }
+        if flattened.len() == 1 {
+            return flattened.pop().unwrap();
+        }
+        let warnings = flattened.iter().filter(|e| e.is_warning() && !e.context.denied).count();
+        let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+        let mut message = plural(flattened.len() - warnings, "error");
+        if warnings > 0 {
+            message = format!("{}, {}", message, plural(warnings, "warning"));
+        }
+        let mut error = Self::new(LashErrorType::MultipleErrors, message);
+        error.context.errors = flattened;
+        error
+    }
+
     pub fn new_church_num_error() -> Self {
-        LashError {
-            error_type: LashErrorType::ChurchNumError,
-            message: "church numerals disabled".to_owned(),
+        Self::new(LashErrorType::ChurchNumError, "church numerals disabled".to_owned())
+    }
+
+    pub fn new_data_error(message: String) -> Self {
+        Self::new(LashErrorType::DataError, message)
+    }
+
+    pub fn new_free_variable_warning(name: &str, suggestion: Option<&str>) -> Self {
+        let warning = Self::new(LashErrorType::FreeVariableWarning, format!("'{}' is not defined", name));
+        match suggestion {
+            Some(suggestion) => warning.with_help(format!("did you mean `{}`?", suggestion)),
+            None => warning,
         }
     }
 
@@ -47,63 +137,169 @@ impl LashError {
             Some(e) => format!("({})", e),
             None => String::new(),
         };
-        LashError {
-            error_type: LashErrorType::FileError,
-            message: format!("unable to open file '{}' {}", file.to_string_lossy(), error_msg),
-        }
+        Self::new(LashErrorType::FileError, format!("unable to open file '{}' {}", file.to_string_lossy(), error_msg))
     }
 
     #[cfg(not(feature = "std"))]
     pub fn new_not_found_error(file: &str) -> Self {
-        LashError {
-            error_type: LashErrorType::NotFoundError,
-            message: format!("{}", file),
-        }
+        Self::new(LashErrorType::NotFoundError, format!("{}", file))
     }
 
-    pub fn new_macro_arg_error(m: Macro, args_given: usize, args_expected: usize) -> Self {
-        LashError {
-            error_type: LashErrorType::MacroArgError,
-            message: format!("macro {} expects {} arguments, but {} were given", m, args_given, args_expected),
-        }
+    pub fn new_interrupt_error(nreductions: usize) -> Self {
+        Self::new(LashErrorType::InterruptError, format!("normalization interrupted after {} reductions", nreductions))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new_interrupt_handler_error(message: String) -> Self {
+        Self::new(LashErrorType::InterruptError, format!("unable to set interrupt handler ({})", message))
+    }
+
+    pub fn new_macro_arg_error(m: Macro, args_expected: usize, args_given: usize) -> Self {
+        Self::new(LashErrorType::MacroArgError, format!("macro {} expects {} arguments, but {} were given", m, args_expected, args_given))
+    }
+
+    pub fn new_redefinition_warning(name: &str) -> Self {
+        Self::new(LashErrorType::RedefinitionWarning, format!("'{}' is already defined", name))
+    }
+
+    pub fn new_shadowing_warning(name: &str) -> Self {
+        Self::new(LashErrorType::ShadowingWarning, format!("variable '{}' shadows the named term of the same name", name))
     }
 
     pub fn new_syntax_error<T: RuleType>(err: pest::error::Error<T>) -> Self {
-        LashError {
-            error_type: LashErrorType::SyntaxError,
-            message: format!("\n{}", err),
-        }
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(pos) => pos,
+            LineColLocation::Span(start, _) => start,
+        };
+        let mut error = Self::new(LashErrorType::SyntaxError, err.variant.message().into_owned());
+        error.context.span = Some(Span { line, column });
+        // pest marks the end of the line with a visible line feed
+        error.context.source = Some(err.line().trim_end_matches(['␊', '␍']).to_owned());
+        error
     }
 
-    pub fn new_set_key_error(key: &str) -> Self {
-        LashError {
-            error_type: LashErrorType::SetKeyError,
-            message: format!("unknown key '{}'", key),
-        }
+    pub fn new_set_key_error(key: &str, keys: &[&str]) -> Self {
+        let error = Self::new(LashErrorType::SetKeyError, format!("unknown key '{}'", key));
+        let error = match closest(key, keys.iter().copied()) {
+            Some(suggestion) => error.with_help(format!("did you mean `{}`?", suggestion)),
+            None => error,
+        };
+        error.with_help(format!("valid keys are {}", keys.join(", ")))
     }
 
-    pub fn new_set_value_error(value: &str) -> Self {
-        LashError {
-            error_type: LashErrorType::SetValueError,
-            message: format!("unknown value '{}'", value),
-        }
+    pub fn new_set_value_error(key: &str, value: &str, expected: &str) -> Self {
+        Self::new(LashErrorType::SetValueError, format!("invalid value '{}' for '{}'", value, key))
+            .with_help(format!("`{}` expects {}", key, expected))
+    }
+
+    pub fn new_size_error(max_size: usize, size: usize, nreductions: usize) -> Self {
+        Self::new(LashErrorType::SizeError, format!("term grew to {} nodes after reduction {} (maximum is {})", size, nreductions, max_size))
+    }
+
+    pub fn new_timeout_error(timeout: Duration, nreductions: usize) -> Self {
+        #[cfg(feature = "std")]
+        let timeout = humantime::format_duration(timeout).to_string();
+        #[cfg(not(feature = "std"))]
+        let timeout = format!("{}ms", timeout.as_millis());
+        Self::new(LashErrorType::TimeoutError, format!("normalization exceeded timeout of {} after {} reductions", timeout, nreductions))
     }
 
     pub fn new_unknown_macro_error(name: &str) -> Self {
-        LashError {
-            error_type: LashErrorType::UnknownMacroError,
-            message: format!("unknown macro '{}'", name),
+        let error = Self::new(LashErrorType::UnknownMacroError, format!("unknown macro '{}'", name));
+        let names: Vec<String> = Macro::macros().iter().map(|m| m.to_string()).collect();
+        match closest(name, names.iter().map(String::as_str)) {
+            Some(suggestion) => error.with_help(format!("did you mean `!{}`?", suggestion)),
+            None => error.with_help("`!macros` lists the available macros".to_owned()),
+        }
+    }
+
+    pub fn new_assertion_error(message: String) -> Self {
+        Self::new(LashErrorType::AssertionError, message)
+    }
+
+    pub fn new_fuel_error(nreductions: usize) -> Self {
+        Self::new(LashErrorType::FuelError, format!("no normal form after {} reductions", nreductions))
+    }
+
+    pub fn new_import_cycle_error(file: &str) -> Self {
+        Self::new(LashErrorType::ImportError, format!("'{}' is imported while it is being imported", file))
+    }
+
+    pub fn new_output_mismatch_error(diff: String) -> Self {
+        Self::new(LashErrorType::OutputMismatchError,
+            format!("output differs from the expected output (-expected +actual)\n{}", diff))
+    }
+
+    pub fn new_scope_error(message: String) -> Self {
+        Self::new(LashErrorType::ScopeError, message)
+    }
+
+    pub fn new_unknown_name_error(name: &str, suggestion: Option<&str>) -> Self {
+        let error = Self::new(LashErrorType::UnknownNameError, format!("'{}' is not a named term", name));
+        match suggestion {
+            Some(suggestion) => error.with_help(format!("did you mean `{}`?", suggestion)),
+            None => error,
         }
     }
 
     #[cfg(not(feature = "std"))]
     pub fn new_not_supported_error(message: String) -> Self {
-        LashError {
-            error_type: LashErrorType::NotSupportedError,
-            message,
+        Self::new(LashErrorType::NotSupportedError, message)
+    }
+
+    /// Set the file the error occurred in, unless it is already known
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.context.file.get_or_insert_with(|| file.to_owned());
+        self.context.errors = self.context.errors.into_iter()
+            .map(|e| e.with_file(file))
+            .collect();
+        self
+    }
+
+    /// Add a hint on how to fix the error
+    pub fn with_help(mut self, help: String) -> Self {
+        self.context.help.push(help);
+        self
+    }
+
+    /// Keep the line of `source` the error points at, so it can be shown along with the error
+    pub fn with_source(mut self, source: &str) -> Self {
+        if let (Some(span), None) = (self.context.span, &self.context.source) {
+            self.context.source = source.lines().nth(span.line - 1).map(ToOwned::to_owned);
         }
+        self.context.errors = self.context.errors.into_iter()
+            .map(|e| e.with_source(source))
+            .collect();
+        self
+    }
+
+    /// Report a warning as an error
+    pub fn deny(mut self) -> Self {
+        self.context.denied = true;
+        self.with_help("warnings are treated as errors because of `@set warnings error`".to_owned())
     }
 
+    pub fn is_warning(&self) -> bool {
+        use LashErrorType::*;
+        matches!(self.error_type, FreeVariableWarning | RedefinitionWarning | ShadowingWarning)
+    }
+
+    /// Whether the user asked to stop, so no further statements should be run
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self.error_type, LashErrorType::InterruptError)
+    }
+
+    pub fn is_out_of_fuel(&self) -> bool {
+        matches!(self.error_type, LashErrorType::FuelError)
+    }
+
+    /// Set the position the error occurred at, unless it is already known
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        if self.context.span.is_none() && self.context.errors.is_empty() {
+            self.context.span = span;
+        }
+        self
+    }
 
     pub fn resolve(&self) {
         #[cfg(feature = "std")]
@@ -119,29 +315,136 @@ impl LashError {
 impl Display for LashError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use LashErrorType::*;
-        let prefix = match self.error_type {
-            ChurchNumError => "Church Numeral Error",
-            FileError => "File Error",
-            FormatError => "Format Error",
-            MacroArgError => "Macro Argument Error",
-            SyntaxError => "Syntax Error",
-            SetKeyError => "Set Key Error",
-            SetValueError => "Set Value Error",
-            UnknownMacroError => "Unknown Macro Error",
+        if let MultipleErrors = self.error_type {
+            for error in &self.context.errors {
+                writeln!(f, "{}\n", error)?;
+            }
+            return write!(f, "{}", self.message);
+        }
+
+        let (code, prefix) = match self.error_type {
+            ChurchNumError => ("E01", "Church Numeral Error"),
+            DataError => ("E02", "Data Error"),
+            FileError => ("E03", "File Error"),
+            FormatError => ("E04", "Format Error"),
+            FreeVariableWarning => ("W01", "Free Variable"),
+            InterruptError => ("E05", "Interrupted"),
+            MacroArgError => ("E06", "Macro Argument Error"),
+            MultipleErrors => unreachable!(),
+            RedefinitionWarning => ("W03", "Redefinition"),
+            SyntaxError => ("E07", "Syntax Error"),
+            SetKeyError => ("E08", "Set Key Error"),
+            SetValueError => ("E09", "Set Value Error"),
+            ShadowingWarning => ("W02", "Shadowing"),
+            SizeError => ("E10", "Size Error"),
+            TimeoutError => ("E11", "Timeout Error"),
+            UnknownMacroError => ("E12", "Unknown Macro Error"),
+            UnknownNameError => ("E15", "Unknown Name Error"),
+            ImportError => ("E16", "Import Error"),
+            ScopeError => ("E17", "Scope Error"),
+            FuelError => ("E18", "Out of Fuel"),
+            AssertionError => ("E19", "Assertion Failed"),
+            OutputMismatchError => ("E20", "Output Mismatch"),
             #[cfg(not(feature = "std"))]
-            NotFoundError => "Not Found",
+            NotFoundError => ("E13", "Not Found"),
             #[cfg(not(feature = "std"))]
-            NotSupportedError => "Not supported",
+            NotSupportedError => ("E14", "Not supported"),
         };
-        write!(f, "{}: {}", prefix, self.message)
+        let severity = if self.is_warning() && !self.context.denied { "warning" } else { "error" };
+        write!(f, "{}[{}]: {}: {}", severity, code, prefix, self.message)?;
+
+        let gutter = " ".repeat(self.context.span.map(|s| s.line.to_string().len()).unwrap_or(0));
+        match (&self.context.file, self.context.span) {
+            (Some(file), Some(span)) => write!(f, "\n{}--> {}:{}", gutter, file, span)?,
+            (Some(file), None) => write!(f, "\n{}--> {}", gutter, file)?,
+            (None, Some(span)) => write!(f, "\n{}--> {}", gutter, span)?,
+            (None, None) => (),
+        }
+        if let (Some(span), Some(source)) = (self.context.span, &self.context.source) {
+            let indent: String = source.chars()
+                .take(span.column - 1)
+                .map(|c| if c == '\t' { c } else { ' ' })
+                .collect();
+            let underline = "^".repeat(token_length(source, span.column));
+            write!(f, "\n{} |", gutter)?;
+            write!(f, "\n{} | {}", span.line, source.trim_end())?;
+            write!(f, "\n{} | {}{}", gutter, indent, underline)?;
+        }
+        for help in &self.context.help {
+            write!(f, "\n{} = help: {}", gutter, help)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of characters to underline for an error at `column`, which covers the word starting there
+fn token_length(line: &str, column: usize) -> usize {
+    let length = line.chars()
+        .skip(column - 1)
+        .take_while(|c| !c.is_whitespace() && !"()[]<>,;.\\\"".contains(*c))
+        .count();
+    length.max(1)
+}
+
+/// Find the candidate that is most likely meant by a misspelled `name`
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let max_distance = (length / 3).max(1);
+    candidates.into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        // replacing the whole name is no suggestion at all
+        .filter(|(distance, _)| *distance <= max_distance && *distance < length)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
     }
+    row[b.len()]
 }
 
 impl From<core::fmt::Error> for LashError {
     fn from(value: core::fmt::Error) -> Self {
-        LashError {
-            error_type: LashErrorType::FormatError,
-            message: value.to_string(),
+        Self::new(LashErrorType::FormatError, value.to_string())
+    }
+}
+
+impl FromStr for WarningLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(WarningLevel::Error),
+            "warn" => Ok(WarningLevel::Warn),
+            "off" => Ok(WarningLevel::Off),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for WarningLevel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WarningLevel::Error => write!(f, "error"),
+            WarningLevel::Warn => write!(f, "warn"),
+            WarningLevel::Off => write!(f, "off"),
         }
     }
 }
+
+impl Display for Span {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,126 @@
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map::Entry;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::lambda::{LambdaNode, LambdaTree, Shared, Step, WeakTree};
+use crate::r#macro::Macro;
+
+
+/// Size of the table below which dropped terms are not cleaned up
+const MIN_COLLECT: usize = 1024;
+
+
+/// Identifies a node by its contents, with children identified by their (interned) address.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum NodeKey {
+    Abstraction(String, usize),
+    Application(usize, usize),
+    Let(String, usize, usize),
+    Macro(Macro, Vec<usize>),
+    Match(Vec<(String, usize)>, Vec<usize>),
+    Named(usize),
+    Variable(String),
+    ChurchNum(u32),
+}
+
+impl NodeKey {
+    fn of(node: &LambdaNode) -> Self {
+        use LambdaNode::*;
+        match node {
+            Abstraction(var, inner) => NodeKey::Abstraction(var.clone(), inner.addr()),
+            Application(left, right) => NodeKey::Application(left.addr(), right.addr()),
+            Let(var, value, body) => NodeKey::Let(var.clone(), value.addr(), body.addr()),
+            Macro(m, terms) => NodeKey::Macro(*m, terms.iter().map(|t| t.addr()).collect()),
+            Match(arms, terms) => NodeKey::Match(arms.clone(), terms.iter().map(|t| t.addr()).collect()),
+            Named(named) => NodeKey::Named(Shared::as_ptr(named) as usize),
+            Variable(name) => NodeKey::Variable(name.clone()),
+            ChurchNum(d) => NodeKey::ChurchNum(*d),
+        }
+    }
+}
+
+/// Hash-consing table that makes structurally identical terms share one allocation.
+///
+/// Interned terms can be compared with [`LambdaTree::ptr_eq`] in constant time.
+/// Positions in the source are not part of the key: an interned term keeps its own position,
+/// while its subterms may come from an earlier term with the same structure.
+/// The table does not keep terms alive: once a term is dropped everywhere else its entry is
+/// dead and gets replaced by the next term with the same key. Dead entries are removed
+/// whenever the table has doubled in size since the last cleanup.
+#[derive(Debug, Default)]
+pub struct TermTable {
+    nodes: BTreeMap<NodeKey, WeakTree>,
+    /// Number of entries at which dead entries are removed next
+    next_collect: usize,
+}
+
+impl TermTable {
+    pub fn new() -> Self {
+        TermTable {
+            nodes: BTreeMap::new(),
+            next_collect: MIN_COLLECT,
+        }
+    }
+
+    /// Return the shared representative of `term`, interning all of its subterms.
+    ///
+    /// Subterms that are already interned are not visited again, so interning a term built
+    /// from interned parts takes time in the number of new nodes.
+    pub fn intern(&mut self, term: &LambdaTree) -> LambdaTree {
+        let mut steps = vec![Step::Visit(term.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            let interned = match step {
+                Step::Visit(term) => match self.nodes.get(&NodeKey::of(term.node())) {
+                    Some(existing) if existing.points_to(&term) => term,
+                    _ => if Step::descend(&term, &mut steps) {
+                        continue;
+                    } else {
+                        self.representative(term)
+                    },
+                },
+                step => match step.assemble(&mut results) {
+                    Some(term) => self.representative(term),
+                    None => continue,
+                },
+            };
+            results.push(interned);
+        }
+        if self.nodes.len() >= self.next_collect {
+            self.collect();
+        }
+        results.pop().unwrap()
+    }
+
+    /// The live term with the same key as `term`, or `term` itself if there is none
+    fn representative(&mut self, term: LambdaTree) -> LambdaTree {
+        match self.nodes.entry(NodeKey::of(term.node())) {
+            Entry::Occupied(mut entry) => match entry.get().upgrade() {
+                Some(existing) => existing.at_optional(term.span()),
+                None => {
+                    entry.insert(term.downgrade());
+                    term
+                },
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(term.downgrade());
+                term
+            },
+        }
+    }
+
+    /// Remove the entries of terms that have been dropped
+    pub fn collect(&mut self) {
+        self.nodes.retain(|_, term| term.is_alive());
+        self.next_collect = (2 * self.nodes.len()).max(MIN_COLLECT);
+    }
+
+    /// Number of interned terms that are still in use
+    pub fn live_terms(&self) -> usize {
+        self.nodes.values().filter(|term| term.is_alive()).count()
+    }
+}
@@ -4,18 +4,18 @@ use rustyline::error::*;
 use crate::environment::Environment;
 use crate::interpreter::Interpreter;
 
-const PROMPT: &str = "[λ] ";
-
 pub fn repl<E: Environment>(interpreter: &mut Interpreter<E>) {
     let mut rl = Editor::<()>::new().unwrap();
 
     loop {
-        match rl.readline(PROMPT) {
+        let prompt = interpreter.prompt().to_owned();
+        match rl.readline(&prompt) {
             Ok(input) => {
                 rl.add_history_entry(input.as_str());
-                match interpreter.interpret_line(&input) {
-                    Ok(statement) => println!("\n==> {}\n", statement),
-                    Err(e) => eprintln!("{}\n", e),
+                let result = interpreter.interpret_line(&input);
+                match result {
+                    Ok(_) => println!("{}", interpreter.format_result(&result)),
+                    Err(_) => eprintln!("{}", interpreter.format_result(&result)),
                 }
             },
             Err(ReadlineError::Interrupted) => {
@@ -1,43 +1,124 @@
 extern crate alloc;
 
-use alloc::collections::BTreeMap;
-use alloc::rc::Rc;
-use alloc::string::String;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::borrow::ToOwned;
+use alloc::string::{String, ToString};
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::fmt;
 use core::fmt::Write;
 use core::str;
-use core::str::FromStr;
 
+use crate::assertion::Assertion;
+use crate::context::{ContextFrame, ContextTracker, FrameKind, Namespace};
+use crate::data::{DataType, Encoding};
+use crate::dependencies::{write_names, Binding, DependencyGraph};
 use crate::error::*;
 use crate::environment::*;
-use crate::parsing;
-use crate::strategy::Strategy;
+use crate::hashcons::TermTable;
+use crate::parsing::{self, Associativity, Operator, OperatorTable};
+use crate::settings::{Setting, Value, SETTINGS};
+use crate::strategy::{Limits, Strategy};
 use crate::lambda::*;
 use crate::stdlib::*;
+use crate::typing;
+
 
 
 pub struct Interpreter<E: Environment> {
+    binding: Binding,
     church_num_enabled: bool,
-    named_terms: BTreeMap<String, Rc<NamedTerm>>,
+    /// Scopes that restore settings and definitions when they end
+    context: ContextTracker,
+    /// Data types by the names of their constructors, along with their eliminators
+    data_types: BTreeMap<String, (Shared<DataType>, Shared<NamedTerm>)>,
+    /// Unlinked terms of the late bound named terms
+    definitions: BTreeMap<String, LambdaTree>,
+    dependency_graph: DependencyGraph,
+    encoding: Encoding,
+    /// File whose statements are currently interpreted
+    file: Option<String>,
+    /// Reductions an assertion may perform to reach a normal form
+    fuel: usize,
+    /// Canonical paths of the files that were included, which are not included again
+    included: BTreeSet<String>,
+    /// Canonical paths of the files whose import is in progress
+    importing: Vec<String>,
+    limits: Limits,
+    /// Named terms defined by the imported files, by their canonical paths
+    modules: BTreeMap<String, Module>,
+    named_terms: BTreeMap<String, Shared<NamedTerm>>,
+    operators: OperatorTable,
+    prompt: String,
+    read_back: ReadBack,
     strategy: Strategy,
+    terms: TermTable,
+    warning_level: WarningLevel,
+    /// Warnings of the current statement, which are printed once it is done
+    warnings: Vec<LashError>,
     env: E,
 }
 
 #[derive(Debug, Clone)]
 pub enum InterpreterDirective {
+    Assert(Assertion),
+    Begin,
+    Data(DataType),
+    Deps(Option<String>),
     Echo(String),
+    End,
+    Env(Option<String>),
+    Get(String),
+    Import(Import),
     Include(String),
+    Infix(String, Operator),
     Set(String, String),
+    Settings,
+    Show(String),
+    Undef(String),
+    Unused,
     UseStd,
 }
 
+/// An `@import` of the named terms of a file
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub file: String,
+    /// Prefix of the imported names, `L` binds `MAP` as `L.MAP`
+    pub alias: Option<String>,
+    /// Names to import, all names the file defines if there is no list
+    pub names: Option<Vec<String>>,
+}
+
+/// Named terms a file defines, along with the names they refer to
+type Module = BTreeMap<String, (Shared<NamedTerm>, BTreeSet<String>)>;
+
 
 impl<E: Environment> Interpreter<E> {
     pub fn new(env: E) -> Interpreter<E> {
         Interpreter {
+            binding: Binding::default(),
             church_num_enabled: false,
+            context: ContextTracker::new(),
+            data_types: BTreeMap::new(),
+            definitions: BTreeMap::new(),
+            dependency_graph: DependencyGraph::new(),
+            encoding: Encoding::default(),
+            file: None,
+            fuel: 10_000,
+            included: BTreeSet::new(),
+            importing: Vec::new(),
+            limits: Limits::default(),
+            modules: BTreeMap::new(),
             named_terms: BTreeMap::new(),
+            operators: OperatorTable::default(),
+            prompt: "[λ] ".to_owned(),
+            read_back: ReadBack::default(),
             strategy: Strategy::default(),
+            terms: TermTable::new(),
+            warning_level: WarningLevel::default(),
+            warnings: Vec::new(),
             env,
         }
     }
@@ -45,87 +126,747 @@ impl<E: Environment> Interpreter<E> {
     fn apply_directive(&mut self, directive: InterpreterDirective) -> LashResult<()> {
         use InterpreterDirective::*;
         match directive {
+            Assert(assertion) => self.check_assertion(assertion),
+            Begin => { self.begin(FrameKind::Block); Ok(()) },
+            Data(data_type) => self.declare_data(data_type),
+            Deps(Some(name)) => {
+                let named = self.lookup(&name)?;
+                let dependencies = LambdaTree::new_named(named.clone()).dependencies();
+                Ok(write_names(self.env.stdout(), "Dependencies", "No dependencies", &dependencies)?)
+            },
+            Deps(None) => {
+                let graph = self.dependency_graph().to_string();
+                Ok(writeln!(self.env.stdout(), "{}", graph)?)
+            },
+            Env(pattern) => {
+                let definitions: Vec<parsing::Statement> = self.named_terms.keys()
+                    .filter(|name| pattern.as_ref().is_none_or(|pattern| matches_pattern(pattern, name)))
+                    .filter_map(|name| self.definition(name))
+                    .collect();
+                for definition in definitions {
+                    writeln!(self.env.stdout(), "{}", definition)?;
+                }
+                Ok(())
+            },
+            Show(name) => {
+                let named = self.lookup(&name)?.clone();
+                let definition = self.definition(&name).unwrap();
+                writeln!(self.env.stdout(), "{}", definition)?;
+                match typing::infer(LambdaTree::new_named(named)) {
+                    Ok(t) => writeln!(self.env.stdout(), "Type: {}", t)?,
+                    Err(e) => writeln!(self.env.stdout(), "Cannot infer type: {}", e)?,
+                }
+                Ok(())
+            },
+            Undef(name) => self.undefine(&name),
+            Unused => {
+                let unused = self.dependency_graph.unused();
+                Ok(write_names(self.env.stdout(), "Unused definitions", "No unused definitions", &unused)?)
+            },
             Echo(msg) => { Ok(writeln!(self.env.stdout(), "{}", msg)?) },
+            End => match self.context.top() {
+                Some(frame) if frame.kind == FrameKind::Block => { self.end(); Ok(()) },
+                _ => Err(LashError::new_scope_error("`@end` without `@begin`".to_owned())),
+            },
+            Get(key) => {
+                let value = self.get(&key)?;
+                Ok(writeln!(self.env.stdout(), "{} = {}", key, value)?)
+            },
             Set(key, value) => self.set(&key, &value),
+            Settings => {
+                for setting in SETTINGS {
+                    let value = self.get(setting.key)?.to_string();
+                    writeln!(self.env.stdout(), "{: <10} {: <12} {}", setting.key, value, setting.description)?;
+                }
+                Ok(())
+            },
+            Import(import) => self.import(import),
             Include(file) => self.include(file),
+            Infix(symbol, operator) => { self.operators.declare(symbol, operator); Ok(()) },
             UseStd => self.interpret_std(),
         }
     }
 
+    /// Check an assertion, a failed assertion is an error
+    fn check_assertion(&mut self, assertion: Assertion) -> LashResult<()> {
+        let failure = match assertion {
+            Assertion::Equal(left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                let left_normal = self.normalize_with_fuel(&left)?.resolve();
+                let right_normal = self.normalize_with_fuel(&right)?.resolve();
+                if left_normal.alpha_eq(&right_normal) {
+                    return Ok(());
+                }
+                LashError::new_assertion_error(format!("`{}` is not equal to `{}`", left, right))
+                    .with_help(format!("their normal forms are `{}` and `{}`", left_normal, right_normal))
+            },
+            Assertion::AlphaEqual(left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                if left.resolve().alpha_eq(&right.resolve()) {
+                    return Ok(());
+                }
+                LashError::new_assertion_error(format!("`{}` is not alpha equivalent to `{}`", left, right))
+            },
+            Assertion::Type(term, expected) => {
+                let term = self.evaluate(term)?;
+                match typing::infer(term.clone()) {
+                    Ok(ty) if ty.matches(&expected) => return Ok(()),
+                    Ok(ty) => LashError::new_assertion_error(format!("`{}` has type `{}`, not `{}`", term, ty, expected)),
+                    Err(e) => LashError::new_assertion_error(format!("cannot infer a type for `{}`", term))
+                        .with_help(e),
+                }
+            },
+            Assertion::Diverges(term) => {
+                let term = self.evaluate(term)?;
+                let limits = Limits { max_reductions: Some(self.fuel), ..self.limits };
+                match self.strategy.normalize(term.clone(), false, &limits, &mut self.env) {
+                    Err(e) if e.is_out_of_fuel() => return Ok(()),
+                    Err(e) => return Err(e),
+                    Ok((normal, count)) => LashError::new_assertion_error(format!("`{}` has the normal form `{}`", term, normal))
+                        .with_help(format!("it is reached after {} reductions", count)),
+                }
+            },
+        };
+        Err(failure)
+    }
+
+    /// Normalize a term of an assertion, which fails if it takes more reductions than the fuel
+    fn normalize_with_fuel(&mut self, term: &LambdaTree) -> LashResult<LambdaTree> {
+        let limits = Limits { max_reductions: Some(self.fuel), ..self.limits };
+        match self.strategy.normalize(term.clone(), false, &limits, &mut self.env) {
+            Ok((normal, _)) => Ok(normal),
+            Err(e) if e.is_out_of_fuel() => Err(LashError::new_assertion_error(format!("`{}` has no normal form within {} reductions", term, self.fuel))
+                .with_help("the limit can be raised with `@set fuel <n>`".to_owned())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The named term `name`, with a suggestion for unknown names
+    fn lookup(&self, name: &str) -> LashResult<&Shared<NamedTerm>> {
+        self.named_terms.get(name).ok_or_else(|| {
+            LashError::new_unknown_name_error(name, closest(name, self.named_terms.keys().map(String::as_str)))
+        })
+    }
+
+    /// The statement that defines `name`
+    ///
+    /// Late bound and recursive terms are shown with the names they use instead of the terms
+    /// these are linked to.
+    fn definition(&self, name: &str) -> Option<parsing::Statement> {
+        use parsing::Statement::*;
+        if let Some(term) = self.definitions.get(name) {
+            return Some(Assignment(name.to_owned(), term.clone()));
+        }
+        let named = self.named_terms.get(name)?;
+        Some(match named.body() {
+            Some(body) => RecAssignment(vec![(name.to_owned(), body)]),
+            None => Assignment(name.to_owned(), named.term()),
+        })
+    }
+
+    /// Remove a named term, late bound terms using it are linked again without it
+    pub fn undefine(&mut self, name: &str) -> LashResult<()> {
+        self.lookup(name)?;
+        self.named_terms.remove(name);
+        self.definitions.remove(name);
+        self.dependency_graph.remove(name);
+        self.relink(&[name.to_owned()]);
+        Ok(())
+    }
+
+    /// Define the constructors and the eliminator of a data type as named terms
+    pub fn declare_data(&mut self, data_type: DataType) -> LashResult<()> {
+        let constructors = data_type.constructors();
+        for (i, constructor) in constructors.iter().enumerate() {
+            if constructors[..i].iter().any(|c| c.name() == constructor.name()) {
+                return Err(LashError::new_data_error(format!("constructor '{}' is declared more than once", constructor.name())));
+            }
+        }
+
+        let mut definitions = data_type.definitions(self.encoding);
+        for (name, term) in &definitions {
+            self.check_redefinition(name, term)?;
+        }
+        let (name, term) = definitions.pop().unwrap();
+        let eliminator = Shared::new(NamedTerm::new(name, term));
+        let mut group = vec![(eliminator.clone(), BTreeSet::new())];
+        for (name, term) in definitions {
+            group.push((Shared::new(NamedTerm::new(name, term)), BTreeSet::new()));
+        }
+        self.define_early(group);
+
+        let data_type = Shared::new(data_type);
+        for constructor in data_type.constructors() {
+            self.data_types.insert(constructor.name().to_owned(), (data_type.clone(), eliminator.clone()));
+        }
+        Ok(())
+    }
+
+    /// Turn a `match` into an application of the eliminator, with the handlers in declaration order
+    fn desugar_match(&self, arms: &[(String, usize)], mut terms: Vec<LambdaTree>) -> LashResult<LambdaTree> {
+        let (data_type, eliminator) = self.data_types.get(&arms[0].0).ok_or_else(|| {
+            let error = LashError::new_data_error(format!("unknown constructor '{}'", arms[0].0));
+            match closest(&arms[0].0, self.data_types.keys().map(String::as_str)) {
+                Some(suggestion) => error.with_help(format!("did you mean `{}`?", suggestion)),
+                None => error,
+            }
+        })?;
+        let constructors = data_type.constructors();
+        let mut handlers = vec![None; constructors.len()];
+        for ((name, nfields), handler) in arms.iter().zip(terms.drain(1..)) {
+            let Some(i) = constructors.iter().position(|c| c.name() == name) else {
+                return Err(LashError::new_data_error(format!("'{}' is not a constructor of {}", name, data_type.name())));
+            };
+            if *nfields != constructors[i].arity() {
+                return Err(LashError::new_data_error(format!("constructor '{}' has {} fields, but {} were given",
+                            name, constructors[i].arity(), nfields)));
+            }
+            if handlers[i].replace(handler).is_some() {
+                return Err(LashError::new_data_error(format!("constructor '{}' is matched more than once", name)));
+            }
+        }
+
+        let mut term = LambdaTree::new_application(LambdaTree::new_named(eliminator.clone()), terms.pop().unwrap());
+        for (constructor, handler) in constructors.iter().zip(handlers) {
+            let handler = handler.ok_or_else(|| LashError::new_data_error(format!("missing case for constructor '{}'", constructor.name())))?;
+            term = LambdaTree::new_application(term, handler);
+        }
+        Ok(term)
+    }
+
+    /// Interpret a file, unless it was included before
     pub fn include(&mut self, file: String) -> LashResult<()> {
-        let contents = self.env().load(&file)?;
-        self.interpret_contents(&contents)
+        let (path, contents) = self.locate(&file)?;
+        if !self.included.insert(self.env.canonical_path(&path)) {
+            return Ok(());
+        }
+        self.begin(FrameKind::File);
+        let result = self.interpret_scoped(&path, &contents);
+        self.end();
+        result
+    }
+
+    /// Bind the named terms a file defines, without the other effects of interpreting it
+    ///
+    /// The file is interpreted only once, later imports reuse the named terms it defined.
+    pub fn import(&mut self, import: Import) -> LashResult<()> {
+        let (path, contents) = self.locate(&import.file)?;
+        let canonical = self.env.canonical_path(&path);
+        let module = match self.modules.get(&canonical) {
+            Some(module) => module.clone(),
+            None => {
+                if self.importing.contains(&canonical) {
+                    return Err(LashError::new_import_cycle_error(&path));
+                }
+                self.importing.push(canonical.clone());
+                let module = self.interpret_module(&path, &contents);
+                self.importing.pop();
+                let module = module?;
+                self.modules.insert(canonical, module.clone());
+                module
+            },
+        };
+
+        let names: Vec<String> = match import.names {
+            Some(names) => names,
+            None => module.keys().cloned().collect(),
+        };
+        let qualify = |name: &str| match &import.alias {
+            Some(alias) => format!("{}.{}", alias, name),
+            None => name.to_owned(),
+        };
+        // qualify the references between the terms of the module as well
+        let renamed: BTreeMap<&String, Shared<NamedTerm>> = match &import.alias {
+            Some(_) => {
+                let terms: Vec<Shared<NamedTerm>> = module.values().map(|(named, _)| named.clone()).collect();
+                module.keys().zip(NamedTerm::rename_all(&terms, qualify)).collect()
+            },
+            None => BTreeMap::new(),
+        };
+        let mut group = Vec::new();
+        for name in names {
+            let (named, dependencies) = module.get(&name).ok_or_else(|| {
+                LashError::new_unknown_name_error(&name, closest(&name, module.keys().map(String::as_str)))
+            })?;
+            let qualified = qualify(&name);
+            self.check_redefinition(&qualified, &named.term())?;
+            let named = renamed.get(&name).unwrap_or(named).clone();
+            let dependencies = dependencies.iter()
+                .map(|d| if module.contains_key(d) { qualify(d) } else { d.clone() })
+                .collect();
+            group.push((named, dependencies));
+        }
+        self.define_early(group);
+        Ok(())
+    }
+
+    /// Interpret a file on its own and collect the named terms it defines or changes
+    fn interpret_module(&mut self, path: &str, contents: &str) -> LashResult<Module> {
+        self.begin(FrameKind::Module);
+        let result = self.interpret_scoped(path, contents);
+        let named_terms = &self.context.top().unwrap().namespace.as_ref().unwrap().named_terms;
+        let module = self.named_terms.iter()
+            .filter(|(name, named)| named_terms.get(*name).is_none_or(|previous| !Shared::ptr_eq(previous, named)))
+            .map(|(name, named)| {
+                let dependencies = self.dependency_graph.dependencies(name).cloned().unwrap_or_default();
+                (name.clone(), (named.clone(), dependencies))
+            })
+            .collect();
+        self.end();
+        result.map(|()| module)
+    }
+
+    /// Open a scope, which restores the current settings and, unless it is a file, the current
+    /// definitions when it ends
+    fn begin(&mut self, kind: FrameKind) {
+        let namespace = (kind != FrameKind::File).then(|| Namespace {
+            data_types: self.data_types.clone(),
+            definitions: self.definitions.clone(),
+            dependency_graph: self.dependency_graph.clone(),
+            included: self.included.clone(),
+            named_terms: self.named_terms.clone(),
+            operators: self.operators.clone(),
+        });
+        let settings = SETTINGS.iter()
+            .map(|setting| (setting.key, self.get(setting.key).unwrap()))
+            .collect();
+        self.context.push_frame(ContextFrame { kind, settings, namespace });
+    }
+
+    /// Close the innermost scope
+    fn end(&mut self) {
+        let Some(frame) = self.context.pop_frame() else { return };
+        for (key, value) in frame.settings {
+            self.set_value(key, value);
+        }
+        if let Some(namespace) = frame.namespace {
+            self.data_types = namespace.data_types;
+            self.definitions = namespace.definitions;
+            self.dependency_graph = namespace.dependency_graph;
+            self.included = namespace.included;
+            self.named_terms = namespace.named_terms;
+            self.operators = namespace.operators;
+        }
+    }
+
+    /// Interpret a file and close the blocks it leaves open
+    fn interpret_scoped(&mut self, file: &str, contents: &str) -> LashResult<()> {
+        let depth = self.context.depth();
+        let result = self.interpret_source(file, contents);
+        if self.context.depth() == depth {
+            return result;
+        }
+        while self.context.depth() > depth {
+            self.end();
+        }
+        let error = LashError::new_scope_error("`@begin` without `@end`".to_owned()).with_file(file);
+        match result {
+            Ok(()) => Err(error),
+            Err(e) => Err(LashError::new_multiple(vec![e, error])),
+        }
+    }
+
+    /// Find the file an `@include` or `@import` refers to and load it
+    ///
+    /// Relative paths are looked up in the directory of the current file first and then in
+    /// the directories of the search path.
+    fn locate(&mut self, file: &str) -> LashResult<(String, String)> {
+        let mut candidates = Vec::new();
+        if file.starts_with('/') {
+            candidates.push(file.to_owned());
+        } else {
+            match self.file.as_deref().and_then(|current| current.rsplit_once('/')) {
+                Some((dir, _)) => candidates.push(format!("{}/{}", dir, file)),
+                None => candidates.push(file.to_owned()),
+            }
+            for dir in self.env.search_path() {
+                candidates.push(format!("{}/{}", dir.trim_end_matches('/'), file));
+            }
+        }
+
+        let mut first_error = None;
+        for candidate in candidates {
+            match self.env.load(&candidate) {
+                Ok(contents) => return Ok((candidate, contents)),
+                Err(e) => { first_error.get_or_insert(e); },
+            }
+        }
+        Err(first_error.unwrap())
+    }
+
+    /// Interpret the contents of `file`, which is the file relative includes are resolved against
+    fn interpret_source(&mut self, file: &str, contents: &str) -> LashResult<()> {
+        let outer = self.file.replace(file.to_owned());
+        let result = self.interpret_statements(contents)
+            .map_err(|e| e.with_file(file));
+        self.file = outer;
+        result
     }
 
     pub fn interpret_contents(&mut self, content: &str) -> LashResult<()> {
-        use parsing::Statement::*;
-        let statements = parsing::parse_statements(content)?;
+        let result = self.interpret_statements(content);
+        self.print_warnings();
+        result
+    }
 
-        for statement in statements {
-            match statement {
-                Assignment(name, term) => {
-                    let term = self.process_lambda_term(term)?;
-                    self.named_terms.insert(name.clone(), Rc::new(NamedTerm::new(name, term)));
+    /// Interpret all statements of `content`, keeping their warnings in order with their errors
+    ///
+    /// If no statement failed, the warnings are left to be printed by the caller.
+    fn interpret_statements(&mut self, content: &str) -> LashResult<()> {
+        let mut statements = parsing::StatementParser::new(content)?;
+        let mut diagnostics = Vec::new();
+        let mut failed = false;
+
+        // errors are collected, so a single run reports all statements that failed
+        while let Some(statement) = statements.next_statement(&self.operators) {
+            let result = statement.and_then(|(span, statement)| {
+                let result = self.execute(statement).map_err(|e| e.with_span(Some(span)));
+                self.locate_warnings(Some(span), content);
+                result
+            });
+            diagnostics.append(&mut self.warnings);
+            match result {
+                Err(e) if e.is_interrupt() => {
+                    self.warnings = diagnostics;
+                    return Err(e);
+                },
+                Err(e) => {
+                    failed = true;
+                    diagnostics.push(e);
                 },
-                Lambda(term) => { self.process_lambda_term(term)?; },
-                Directive(directive) => self.apply_directive(directive)?,
+                Ok(()) => (),
             }
         }
 
+        if failed {
+            Err(LashError::new_multiple(diagnostics).with_source(content))
+        } else {
+            self.warnings = diagnostics;
+            Ok(())
+        }
+    }
+
+    fn execute(&mut self, statement: parsing::Statement) -> LashResult<()> {
+        use parsing::Statement::*;
+        match statement {
+            Assignment(name, term) => { self.assign(name, term)?; },
+            Lambda(term) => { self.evaluate(term)?; },
+            Directive(directive) => self.apply_directive(directive)?,
+            RecAssignment(bindings) => { self.define_recursive(bindings)?; },
+        }
         Ok(())
     }
 
+    /// Process a term that is not bound to a name
+    fn evaluate(&mut self, term: LambdaTree) -> LashResult<LambdaTree> {
+        self.check_term(&term, None)?;
+        self.process_lambda_term(term)
+    }
+
+    /// Process the term of an assignment and bind it to `name`
+    fn assign(&mut self, name: String, term: LambdaTree) -> LashResult<LambdaTree> {
+        let late = self.binding == Binding::Late;
+        // late bound definitions may use names that are only defined afterwards
+        self.check_term(&term, if late { None } else { Some(&[]) })?;
+        let term = self.process_lambda_term(term)?;
+        if late {
+            let term = term.unlink(&self.named_terms);
+            self.check_redefinition(&name, &term)?;
+            self.define_late(vec![(name, term.clone())]);
+            Ok(term)
+        } else {
+            self.check_redefinition(&name, &term)?;
+            let dependencies = term.references();
+            self.define_early(vec![(Shared::new(NamedTerm::new(name, term.clone())), dependencies)]);
+            Ok(term)
+        }
+    }
+
+    /// Bind named terms, which keep referring to the named terms they were defined with
+    fn define_early(&mut self, group: Vec<(Shared<NamedTerm>, BTreeSet<String>)>) {
+        let names: Vec<String> = group.iter().map(|(named, _)| named.name().to_owned()).collect();
+        for (named, dependencies) in group {
+            let name = named.name().to_owned();
+            self.definitions.remove(&name);
+            self.dependency_graph.set(name.clone(), dependencies);
+            self.named_terms.insert(name, named);
+        }
+        self.relink(&names);
+    }
+
+    /// Bind named terms that always refer to the latest definitions of the names they use
+    fn define_late(&mut self, group: Vec<(String, LambdaTree)>) {
+        let names: Vec<String> = group.iter().map(|(name, _)| name.clone()).collect();
+        for (name, term) in group {
+            self.dependency_graph.set(name.clone(), term.free_occurrences().into_keys().collect());
+            self.definitions.insert(name, term);
+        }
+        self.relink(&names);
+    }
+
+    /// Link the late bound named terms that depend on `names` to the current definitions again
+    ///
+    /// Named terms that refer to each other in a cycle are defined as a recursive group.
+    fn relink(&mut self, names: &[String]) {
+        let mut affected = self.dependency_graph.dependents(names.iter().map(String::as_str));
+        affected.extend(names.iter().cloned());
+        affected.retain(|name| self.definitions.contains_key(name));
+
+        for group in self.dependency_graph.components(&affected) {
+            let recursive = group.len() > 1 || self.dependency_graph.dependencies(&group[0])
+                .is_some_and(|d| d.contains(&group[0]));
+            let bound: &[String] = if recursive { &group } else { &[] };
+            let mut bodies = Vec::new();
+            for name in &group {
+                let term = self.definitions[name].clone();
+                let linked = with_bound(term, bound, |term| Ok(term.set_named_terms(&self.named_terms))).unwrap();
+                bodies.push((name.clone(), self.terms.intern(&linked)));
+            }
+            let named_terms = if recursive {
+                NamedTerm::new_recursive(bodies)
+            } else {
+                bodies.into_iter().map(|(name, term)| NamedTerm::new(name, term)).collect()
+            };
+            for named in named_terms {
+                self.named_terms.insert(named.name().to_owned(), Shared::new(named));
+            }
+        }
+    }
+
+    /// Warn about bound variables named like named terms and, in definitions, about free variables
+    ///
+    /// `definition` holds the names a definition may use besides the named terms.
+    fn check_term(&mut self, term: &LambdaTree, definition: Option<&[String]>) -> LashResult<()> {
+        for (var, span) in term.binders() {
+            if self.named_terms.contains_key(&var) {
+                self.warn(LashError::new_shadowing_warning(&var).with_span(span))?;
+            }
+        }
+        let Some(bound) = definition else { return Ok(()) };
+        for (var, span) in term.free_occurrences() {
+            if !self.named_terms.contains_key(&var) && !bound.contains(&var) {
+                let suggestion = closest(&var, self.named_terms.keys().map(String::as_str));
+                self.warn(LashError::new_free_variable_warning(&var, suggestion).with_span(span))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn about a definition replacing a different term of the same name
+    ///
+    /// Late bound definitions are compared by their unlinked terms.
+    fn check_redefinition(&mut self, name: &str, term: &LambdaTree) -> LashResult<()> {
+        let previous = match (self.definitions.get(name), self.named_terms.get(name)) {
+            (Some(definition), _) => definition.clone(),
+            (None, Some(named)) => named.term(),
+            (None, None) => return Ok(()),
+        };
+        if previous.alpha_eq(term) {
+            return Ok(());
+        }
+
+        let (changed, unchanged): (Vec<String>, Vec<String>) = self.dependency_graph.dependents([name])
+            .into_iter()
+            .filter(|dependent| dependent != name)
+            .partition(|dependent| self.definitions.contains_key(dependent));
+        let mut warning = LashError::new_redefinition_warning(name);
+        if !changed.is_empty() {
+            warning = warning.with_help(format!("the new definition is also used by {}", name_list(&changed)));
+        }
+        if !unchanged.is_empty() {
+            warning = warning.with_help(format!("the previous definition is still used by {}", name_list(&unchanged)));
+        }
+        self.warn(warning)
+    }
+
+    /// Report a warning as configured with `@set warnings`
+    fn warn(&mut self, warning: LashError) -> LashResult<()> {
+        match self.warning_level {
+            WarningLevel::Error => return Err(warning.deny()),
+            WarningLevel::Warn => self.warnings.push(warning),
+            WarningLevel::Off => (),
+        }
+        Ok(())
+    }
+
+    /// Attach the statement at `span` in `source` to its warnings, unless they already point elsewhere
+    fn locate_warnings(&mut self, span: Option<Span>, source: &str) {
+        self.warnings = core::mem::take(&mut self.warnings).into_iter()
+            .map(|warning| {
+                let warning = warning.with_span(span).with_source(source);
+                match &self.file {
+                    Some(file) => warning.with_file(file),
+                    None => warning,
+                }
+            })
+            .collect();
+    }
+
+    /// Print the warnings that have not been reported yet
+    fn print_warnings(&mut self) {
+        for warning in core::mem::take(&mut self.warnings) {
+            let _ignored = writeln!(self.env.stderr(), "{}\n", warning);
+        }
+    }
+
     #[cfg(feature = "std")]
     pub fn interpret_file(&mut self, file: std::path::PathBuf) -> LashResult<()> {
         let contents = std::fs::read_to_string(&file)
-            .map_err(|e| LashError::new_file_error(file, Some(e)))?;
-        self.interpret_contents(&contents)
+            .map_err(|e| LashError::new_file_error(file.clone(), Some(e)))?;
+        let name = file.to_string_lossy();
+        self.included.insert(self.env.canonical_path(&name));
+        let result = self.interpret_scoped(&name, &contents);
+        self.print_warnings();
+        result
     }
 
     pub fn interpret_line(&mut self, line: &str) -> LashResult<parsing::Statement> {
+        let result = self.execute_line(line)
+            .map_err(|e| e.with_source(line));
+        self.locate_warnings(None, line);
+        self.print_warnings();
+        result
+    }
+
+    fn execute_line(&mut self, line: &str) -> LashResult<parsing::Statement> {
         use parsing::Statement::*;
-        let statement = parsing::parse_statement(line)?;
+        let statement = parsing::parse_statement(line, &self.operators)?;
 
         match statement.clone() {
-            Assignment(name, term) => {
-                let term = self.process_lambda_term(term)?;
-                self.named_terms.insert(name.clone(), Rc::new(NamedTerm::new(name.clone(), term.clone())));
-                Ok(Assignment(name, term))
-            },
-            Lambda(term) => Ok(Lambda(self.process_lambda_term(term)?)),
+            Assignment(name, term) => Ok(Assignment(name.clone(), self.assign(name, term)?)),
+            Lambda(term) => Ok(Lambda(self.evaluate(term)?)),
             Directive(directive) => { self.apply_directive(directive)?; Ok(statement) },
+            RecAssignment(bindings) => Ok(RecAssignment(self.define_recursive(bindings)?)),
         }
     }
 
+    /// Define a group of mutually recursive terms, returns the processed definitions
+    fn define_recursive(&mut self, bindings: Vec<(String, LambdaTree)>) -> LashResult<Vec<(String, LambdaTree)>> {
+        let names: Vec<String> = bindings.iter().map(|(name, _)| name.clone()).collect();
+        let mut processed = Vec::new();
+        let late = self.binding == Binding::Late;
+        for (_, term) in &bindings {
+            self.check_term(term, if late { None } else { Some(&names) })?;
+        }
+        for (name, term) in bindings {
+            // bind the names of the group, so they are not replaced by previous definitions
+            let term = with_bound(term, &names, |term| self.process_lambda_term(term))?;
+            processed.push((name, term));
+        }
+
+        if late {
+            // the cycle is found again when linking the group
+            let unlinked: Vec<(String, LambdaTree)> = processed.into_iter()
+                .map(|(name, term)| (name, term.unlink(&self.named_terms)))
+                .collect();
+            for (name, term) in &unlinked {
+                self.check_redefinition(name, term)?;
+            }
+            self.define_late(unlinked.clone());
+            return Ok(unlinked);
+        }
+
+        let group = NamedTerm::new_recursive(processed.clone());
+        for named in &group {
+            self.check_redefinition(named.name(), &named.term())?;
+        }
+        let group = group.into_iter()
+            .zip(&processed)
+            .map(|(named, (_, body))| {
+                let mut dependencies = body.references();
+                dependencies.extend(body.free_occurrences().into_keys().filter(|name| names.contains(name)));
+                (Shared::new(named), dependencies)
+            })
+            .collect();
+        self.define_early(group);
+        Ok(processed)
+    }
+
     pub fn interpret_std(&mut self) -> LashResult<()> {
         self.interpret_contents(STD)
     }
 
     fn process_lambda_term(&mut self, term: LambdaTree) -> LashResult<LambdaTree> {
-        if !self.church_num_enabled && term.has_church_nums() {
-            return Err(LashError::new_church_num_error());
+        if !self.church_num_enabled {
+            if let Some(church_num) = term.find_church_num() {
+                return Err(LashError::new_church_num_error().with_span(church_num.span()));
+            }
         }
 
-        let with_named = term.set_named_terms(&self.named_terms);
-        let with_macros = with_named.apply_macros(self)?;
-        Ok(with_macros)
+        let desugared = term.desugar_matches(|arms, terms| self.desugar_match(arms, terms))?;
+        // macros are applied before interning, so their errors point at their own positions
+        let with_macros = desugared.set_named_terms(&self.named_terms).apply_macros(self)?;
+        Ok(self.terms.intern(&with_macros))
     }
 
+    /// Change a setting, `value` is parsed according to the type of the setting
     pub fn set(&mut self, key: &str, value: &str) -> LashResult<()> {
-        match key {
-            "strategy" => match Strategy::from_str(value).ok() {
-                Some(strat) => self.set_strategy(strat),
-                None => return Err(LashError::new_set_value_error(value)),
-            },
-            "numerals" => match str::parse(value).ok() {
-                Some(b) => self.set_church_num_enabled(b),
-                None => return Err(LashError::new_set_value_error(value)),
-            },
-            _ => return Err(LashError::new_set_key_error(key)),
-        }
+        let setting = Setting::find(key)?;
+        let value = setting.parse(value)?;
+        self.set_value(setting.key, value);
         Ok(())
     }
 
+    /// Change a setting to a value of its type
+    fn set_value(&mut self, key: &str, value: Value) {
+        match (key, value) {
+            ("binding", Value::Enum(name)) => self.binding = name.parse().unwrap(),
+            ("encoding", Value::Enum(name)) => self.encoding = name.parse().unwrap(),
+            ("fuel", Value::Integer(fuel)) => self.fuel = fuel,
+            ("maxsize", Value::Off) => self.limits.max_size = None,
+            ("maxsize", Value::Integer(max_size)) => self.limits.max_size = Some(max_size),
+            ("numerals", Value::Bool(b)) => self.set_church_num_enabled(b),
+            ("prompt", Value::String(prompt)) => self.prompt = prompt,
+            ("readback", Value::Enum(name)) => self.read_back = name.parse().unwrap(),
+            ("strategy", Value::Enum(name)) => self.set_strategy(name.parse().unwrap()),
+            ("timeout", Value::Off) => self.limits.timeout = None,
+            ("timeout", Value::Duration(timeout)) => self.limits.timeout = Some(timeout),
+            ("warnings", Value::Enum(name)) => self.warning_level = name.parse().unwrap(),
+            (key, value) => unreachable!("invalid value {:?} for setting '{}'", value, key),
+        }
+    }
+
+    /// The current value of a setting
+    pub fn get(&self, key: &str) -> LashResult<Value> {
+        let setting = Setting::find(key)?;
+        Ok(match setting.key {
+            "binding" => Value::Enum(self.binding.to_string()),
+            "encoding" => Value::Enum(self.encoding.to_string()),
+            "fuel" => Value::Integer(self.fuel),
+            "maxsize" => self.limits.max_size.map_or(Value::Off, Value::Integer),
+            "numerals" => Value::Bool(self.church_num_enabled),
+            "prompt" => Value::String(self.prompt.clone()),
+            "readback" => Value::Enum(self.read_back.to_string()),
+            "strategy" => Value::Enum(self.strategy.to_string()),
+            "timeout" => self.limits.timeout.map_or(Value::Off, Value::Duration),
+            "warnings" => Value::Enum(self.warning_level.to_string()),
+            key => unreachable!("no value for setting '{}'", key),
+        })
+    }
+
+    /// Prompt of the interactive mode
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Which literals are read back when printing terms
+    pub fn read_back(&self) -> ReadBack {
+        self.read_back
+    }
+
+    /// How the interactive mode shows the result of a line
+    pub fn format_result(&self, result: &LashResult<parsing::Statement>) -> String {
+        match result {
+            Ok(statement) => format!("\n==> {}\n", self.read_back.format(statement)),
+            Err(e) => format!("{}\n", e),
+        }
+    }
 
     pub fn set_church_num_enabled(&mut self, b: bool) {
         self.church_num_enabled = b;
@@ -139,20 +880,108 @@ impl<E: Environment> Interpreter<E> {
         self.strategy
     }
 
+    pub fn term_table(&self) -> &TermTable {
+        &self.terms
+    }
+
+    pub fn dependency_graph(&self) -> &DependencyGraph {
+        &self.dependency_graph
+    }
+
     pub fn env(&mut self) -> &mut E {
         &mut self.env
     }
 }
 
+/// Whether `name` matches a pattern in which `*` stands for any text and `?` for any character
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the part of the name it currently covers
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 
 impl fmt::Display for InterpreterDirective {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use InterpreterDirective::*;
         match self {
+            Assert(assertion) => write!(f, "{}", assertion),
+            Begin => write!(f, "@begin"),
+            Data(data_type) => write!(f, "@data {}", data_type),
+            Deps(Some(name)) => write!(f, "@deps {}", name),
+            Deps(None) => write!(f, "@deps"),
+            Env(Some(pattern)) => write!(f, "@env {}", pattern),
+            Env(None) => write!(f, "@env"),
+            Show(name) => write!(f, "@show {}", name),
+            Undef(name) => write!(f, "@undef {}", name),
             Echo(msg) => write!(f, "@echo \"{}\"", msg),
+            End => write!(f, "@end"),
+            Get(key) => write!(f, "@get {}", key),
+            Set(key, value) if value.is_empty() || value.contains(char::is_whitespace) => write!(f, "@set {} \"{}\"", key, value),
             Set(key, value) => write!(f, "@set {} {}", key, value),
+            Settings => write!(f, "@settings"),
+            Import(import) => {
+                write!(f, "@import \"{}\"", import.file)?;
+                if let Some(alias) = &import.alias {
+                    write!(f, " as {}", alias)?;
+                }
+                match &import.names {
+                    Some(names) => write!(f, " ({})", names.join(", ")),
+                    None => Ok(()),
+                }
+            },
             Include(file) => write!(f, "@include \"{}\"", file),
+            Infix(symbol, operator) => {
+                let kind = match operator.associativity {
+                    Associativity::Left => "infixl",
+                    Associativity::Right => "infixr",
+                    Associativity::None => "infix",
+                };
+                write!(f, "@{} {} {} {}", kind, operator.precedence, symbol, operator.term)
+            },
+            Unused => write!(f, "@unused"),
             UseStd => write!(f, "@usestd"),
         }
     }
 }
+
+/// Process `term` with `names` bound around it, so they are not replaced by named terms
+fn with_bound<F>(term: LambdaTree, names: &[String], process: F) -> LashResult<LambdaTree>
+        where F: FnOnce(LambdaTree) -> LashResult<LambdaTree> {
+    let mut wrapped = term;
+    for name in names.iter().rev() {
+        wrapped = LambdaTree::new_abstraction(name.clone(), wrapped);
+    }
+    let mut term = process(wrapped)?;
+    for _ in names {
+        term = match term.node() {
+            LambdaNode::Abstraction(_, inner) => inner.clone(),
+            _ => unreachable!(),
+        };
+    }
+    Ok(term)
+}
+
+fn name_list(names: &[String]) -> String {
+    names.iter()
+        .map(|name| format!("`{}`", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,27 +1,45 @@
 extern crate alloc;
 
 use alloc::borrow::ToOwned;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::rc::Rc;
 use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::format;
 use core::fmt::Display;
-use core::str;
+use core::mem;
+use core::str::{self, FromStr};
 
 use crate::debruijn::DeBruijnNode;
 use crate::environment::Environment;
 use crate::interpreter::Interpreter;
 use crate::r#macro::Macro;
-use crate::error::LashResult;
+use crate::error::{LashResult, Span};
+use crate::stdlib;
 
 
+/// Reference counted pointer for terms
+///
+/// With the `sync` feature this is atomic, so terms can be shared between threads.
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = alloc::rc::Rc<T>;
+#[cfg(feature = "sync")]
+pub type Shared<T> = alloc::sync::Arc<T>;
+#[cfg(not(feature = "sync"))]
+type Weak<T> = alloc::rc::Weak<T>;
+#[cfg(feature = "sync")]
+type Weak<T> = alloc::sync::Weak<T>;
+
 #[derive(Clone, Debug)]
 pub enum LambdaNode {
     Abstraction(String, LambdaTree),
     Application(LambdaTree, LambdaTree),
+    Let(String, LambdaTree, LambdaTree),
     Macro(Macro, Vec<LambdaTree>),
-    Named(Rc<NamedTerm>),
+    /// Arms as constructor and number of fields, followed by the scrutinee and the handlers
+    Match(Vec<(String, usize)>, Vec<LambdaTree>),
+    Named(Shared<NamedTerm>),
     Variable(String),
     ChurchNum(u32),
 }
@@ -30,83 +48,463 @@ pub enum LambdaNode {
 pub struct NamedTerm {
     name: String,
     term: LambdaTree,
+    recursion: Option<(Shared<[RecursiveBinding]>, usize)>,
+}
+
+/// Member of a group of (mutually) recursive definitions
+#[derive(Clone, Debug)]
+struct RecursiveBinding {
+    name: String,
+    /// Definition in which the names of the group occur as free variables
+    body: LambdaTree,
+    /// Closed definition using a fixed-point combinator
+    fixpoint: LambdaTree,
 }
 
+/// Shared node of a term, along with the position in the source it was parsed from
+///
+/// The position is not part of the node, so terms at different positions can share it.
+#[derive(Clone, Debug)]
+pub struct LambdaTree(Shared<SizedNode>, Option<Span>);
+
+/// Reference to a term that does not keep it alive
+#[derive(Clone, Debug)]
+pub(crate) struct WeakTree(Weak<SizedNode>);
+
+/// Node of a term along with the size of the term, see [`LambdaTree::size`]
 #[derive(Clone, Debug)]
-pub struct LambdaTree(Rc<LambdaNode>);
+struct SizedNode {
+    node: LambdaNode,
+    size: usize,
+}
+
+/// Pending work for the explicit-stack traversals over [`LambdaTree`]
+///
+/// Terms may be nested far deeper than the call stack allows, so traversals push
+/// the children they still have to visit together with a marker for rebuilding the
+/// parent once all children are done.
+pub(crate) enum Step {
+    Visit(LambdaTree),
+    /// Marks that the following visits are in the scope of a binder (used for the body of `let`)
+    Bind(String),
+    Abstraction(String),
+    Application,
+    Let(String),
+    Macro(Macro, usize, Option<Span>),
+    Match(Vec<(String, usize)>, Option<Span>),
+}
+
+/// Which literals are recognized when printing terms
+///
+/// Terms are printed with read-back by formatting them with `{:#}`, with `{:+#}` lists of
+/// character codes are printed as strings as well.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReadBack {
+    #[default]
+    Off,
+    /// Lists and pairs
+    Literals,
+    /// Lists, pairs and strings
+    Strings,
+}
+
+/// Literal syntax recognized in terms when printing them with read-back
+enum Sugar<'a> {
+    List(Vec<&'a LambdaTree>),
+    Pair(&'a LambdaTree, &'a LambdaTree),
+    String(String),
+}
+
+/// A term that may be substituted for a variable, along with its free variables
+#[derive(Clone)]
+struct Substitution {
+    name: String,
+    term: LambdaTree,
+    free: Rc<BTreeSet<String>>,
+}
 
 impl NamedTerm {
     pub fn new(name: String, term: LambdaTree) -> Self {
-        NamedTerm { name, term }
+        NamedTerm { name, term, recursion: None }
+    }
+
+    /// Define a group of mutually recursive terms
+    ///
+    /// The names of the group may occur freely in all bodies. Their definitions are compiled
+    /// with the Y combinator: a single term `f` becomes `Y (\f . body)`, a larger group becomes
+    /// a tuple of all bodies under a common fixed point, from which each name projects its part.
+    pub fn new_recursive(bindings: Vec<(String, LambdaTree)>) -> Vec<Self> {
+        use LambdaTree as L;
+        let var = |name: &str| L::new_variable(name.to_owned());
+        let names: Vec<String> = bindings.iter().map(|(name, _)| name.clone()).collect();
+        let free: BTreeSet<String> = bindings.iter()
+            .flat_map(|(_, body)| body.free_variables())
+            .collect();
+        let fresh = |name: &str| {
+            let mut name = name.to_owned();
+            while free.contains(&name) || names.contains(&name) {
+                name = format!("{}'", name);
+            }
+            name
+        };
+
+        // \g . (\x . g (x x)) (\x . g (x x))
+        let half = L::new_abstraction("x".to_owned(),
+            L::new_application(var("g"), L::new_application(var("x"), var("x"))));
+        let y = L::new_abstraction("g".to_owned(), L::new_application(half.clone(), half));
+
+        let fixpoints: Vec<LambdaTree> = if let [(name, body)] = bindings.as_slice() {
+            vec![L::new_application(y, L::new_abstraction(name.clone(), body.clone()))]
+        } else {
+            let (tuple, selector) = (fresh("p"), fresh("s"));
+            let projection = |i: usize| {
+                let mut term = var(&format!("x{}", i));
+                for j in (0..names.len()).rev() {
+                    term = L::new_abstraction(format!("x{}", j), term);
+                }
+                term
+            };
+            let mut tuple_term = var(&selector);
+            for (_, body) in &bindings {
+                let mut body = body.clone();
+                for (i, name) in names.iter().enumerate() {
+                    body = body.substitute(name, L::new_application(var(&tuple), projection(i)));
+                }
+                tuple_term = L::new_application(tuple_term, body);
+            }
+            let group = L::new_application(y, L::new_abstraction(tuple,
+                    L::new_abstraction(selector, tuple_term)));
+            (0..names.len())
+                .map(|i| L::new_application(group.clone(), projection(i)))
+                .collect()
+        };
+
+        let group: Shared<[RecursiveBinding]> = bindings.into_iter()
+            .zip(fixpoints)
+            .map(|((name, body), fixpoint)| RecursiveBinding { name, body, fixpoint })
+            .collect::<Vec<_>>()
+            .into();
+        (0..group.len())
+            .map(|i| Self::member(&group, i))
+            .collect()
+    }
+
+    fn member(group: &Shared<[RecursiveBinding]>, index: usize) -> Self {
+        let binding = &group[index];
+        NamedTerm {
+            name: binding.name.clone(),
+            term: binding.fixpoint.clone(),
+            recursion: Some((group.clone(), index)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Rename the named terms of `group` and make the references between them use the new names
+    ///
+    /// References to named terms outside of `group` are kept. Recursive definitions are defined
+    /// again with the new names, so their bodies show the new names as well.
+    pub fn rename_all(group: &[Shared<NamedTerm>], rename: impl Fn(&str) -> String) -> Vec<Shared<NamedTerm>> {
+        let mut renamed: Vec<Option<Shared<NamedTerm>>> = vec![None; group.len()];
+        // references never form a cycle, so each round renames at least one term
+        while renamed.iter().any(Option::is_none) {
+            for i in 0..group.len() {
+                if renamed[i].is_some() {
+                    continue;
+                }
+                let mut ready = true;
+                let mut relink = |term: &LambdaTree| term.replace_named(|named| {
+                    let j = group.iter().position(|n| Shared::ptr_eq(n, named))?;
+                    ready &= renamed[j].is_some();
+                    renamed[j].clone()
+                });
+                match &group[i].recursion {
+                    None => {
+                        let term = relink(&group[i].term);
+                        if ready {
+                            renamed[i] = Some(Shared::new(NamedTerm::new(rename(&group[i].name), term)));
+                        }
+                    },
+                    Some((members, _)) => {
+                        let bindings: Vec<(String, LambdaTree)> = members.iter()
+                            .map(|binding| {
+                                let body = members.iter().fold(relink(&binding.body), |body, member| {
+                                    body.substitute(&member.name, LambdaTree::new_variable(rename(&member.name)))
+                                });
+                                (rename(&binding.name), body)
+                            })
+                            .collect();
+                        if !ready {
+                            continue;
+                        }
+                        let mut new_members = NamedTerm::new_recursive(bindings).into_iter().map(Some).collect::<Vec<_>>();
+                        // the other members of the group are renamed along with this one
+                        for (j, named) in group.iter().enumerate() {
+                            if let Some((other, index)) = &named.recursion {
+                                if Shared::ptr_eq(other, members) {
+                                    renamed[j] = new_members[*index].take().map(Shared::new);
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+        }
+        renamed.into_iter().map(Option::unwrap).collect()
     }
 
     pub fn term(&self) -> LambdaTree {
         self.term.clone()
     }
+
+    /// The definition of a recursive term, in which the names of its group occur freely
+    pub fn body(&self) -> Option<LambdaTree> {
+        self.recursion.as_ref().map(|(group, index)| group[*index].body.clone())
+    }
+
+    /// The term a reference to this name reduces to
+    ///
+    /// Unlike [`term()`](Self::term), recursive definitions are unfolded only once, with the
+    /// recursive references still showing the names of the group.
+    pub fn unfold(&self) -> LambdaTree {
+        let Some((group, index)) = &self.recursion else { return self.term() };
+        let mut body = group[*index].body.clone();
+        for (i, binding) in group.iter().enumerate() {
+            let named = LambdaNode::Named(Shared::new(Self::member(group, i)));
+            body = body.substitute(&binding.name, LambdaTree::from_node(named));
+        }
+        body
+    }
+}
+
+impl WeakTree {
+    /// The term without a position, unless it has been dropped
+    pub(crate) fn upgrade(&self) -> Option<LambdaTree> {
+        self.0.upgrade().map(|node| LambdaTree(node, None))
+    }
+
+    pub(crate) fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+
+    /// Whether this is a reference to the node of `term`
+    pub(crate) fn points_to(&self, term: &LambdaTree) -> bool {
+        Weak::as_ptr(&self.0) as usize == term.addr()
+    }
+}
+
+impl Step {
+    /// Schedule the children of a compound term, returns `false` for leaves
+    pub(crate) fn descend(term: &LambdaTree, steps: &mut Vec<Step>) -> bool {
+        use LambdaNode::*;
+        match term.node() {
+            Abstraction(var, inner) => {
+                steps.push(Step::Abstraction(var.clone()));
+                steps.push(Step::Visit(inner.clone()));
+            },
+            Application(left_term, right_term) => {
+                steps.push(Step::Application);
+                steps.push(Step::Visit(right_term.clone()));
+                steps.push(Step::Visit(left_term.clone()));
+            },
+            Let(var, value, body) => {
+                steps.push(Step::Let(var.clone()));
+                steps.push(Step::Visit(body.clone()));
+                steps.push(Step::Bind(var.clone()));
+                steps.push(Step::Visit(value.clone()));
+            },
+            Macro(m, terms) => {
+                steps.push(Step::Macro(*m, terms.len(), term.span()));
+                steps.extend(terms.iter().rev().cloned().map(Step::Visit));
+            },
+            Match(arms, terms) => {
+                steps.push(Step::Match(arms.clone(), term.span()));
+                steps.extend(terms.iter().rev().cloned().map(Step::Visit));
+            },
+            Named(_) | Variable(_) | ChurchNum(_) => return false,
+        }
+        true
+    }
+
+    /// Take the processed children off `results` and combine them into a term
+    ///
+    /// Returns `None` for [`Step::Bind`], which does not produce a term.
+    pub(crate) fn assemble(self, results: &mut Vec<LambdaTree>) -> Option<LambdaTree> {
+        let mut span = None;
+        let node = match self {
+            Step::Abstraction(var) => LambdaNode::Abstraction(var, results.pop().unwrap()),
+            Step::Application => {
+                let right_term = results.pop().unwrap();
+                let left_term = results.pop().unwrap();
+                LambdaNode::Application(left_term, right_term)
+            },
+            Step::Let(var) => {
+                let body = results.pop().unwrap();
+                let value = results.pop().unwrap();
+                LambdaNode::Let(var, value, body)
+            },
+            Step::Macro(m, nterms, macro_span) => {
+                span = macro_span;
+                LambdaNode::Macro(m, results.split_off(results.len() - nterms))
+            },
+            Step::Match(arms, match_span) => {
+                span = match_span;
+                let terms = results.split_off(results.len() - arms.len() - 1);
+                LambdaNode::Match(arms, terms)
+            },
+            Step::Bind(_) => return None,
+            Step::Visit(_) => unreachable!(),
+        };
+        Some(LambdaTree::from_node(node).at_optional(span))
+    }
 }
 
 impl LambdaTree {
     pub fn alpha_eq(&self, other: &LambdaTree) -> bool {
-        DeBruijnNode::from(self.clone()) == DeBruijnNode::from(other.clone())
+        self.ptr_eq(other) || DeBruijnNode::from(self.clone()) == DeBruijnNode::from(other.clone())
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Shared::as_ptr(&self.0) as usize
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakTree {
+        WeakTree(Shared::downgrade(&self.0))
+    }
+
+    pub(crate) fn from_node(node: LambdaNode) -> Self {
+        use LambdaNode::*;
+        let size = match &node {
+            Abstraction(_, inner) => inner.size(),
+            Application(left_term, right_term) | Let(_, left_term, right_term) => left_term.size().saturating_add(right_term.size()),
+            Macro(_, terms) | Match(_, terms) => terms.iter().map(LambdaTree::size).fold(0, usize::saturating_add),
+            Named(_) | Variable(_) | ChurchNum(_) => 0,
+        }.saturating_add(1);
+        LambdaTree(Shared::new(SizedNode { node, size }), None)
+    }
+
+    /// Attach the position in the source this term was parsed from
+    pub fn at(self, span: Span) -> Self {
+        LambdaTree(self.0.clone(), Some(span))
+    }
+
+    pub(crate) fn at_optional(self, span: Option<Span>) -> Self {
+        match span {
+            Some(span) => self.at(span),
+            None => self,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.1
     }
 
     pub fn new_abstraction(var: String, term: Self) -> Self {
         use LambdaNode::*;
-        LambdaTree(Rc::new(Abstraction(var, term)))
+        Self::from_node(Abstraction(var, term))
     }
 
     pub fn new_application(left_term: Self, right_term: Self) -> Self {
         use LambdaNode::*;
-        LambdaTree(Rc::new(Application(left_term, right_term)))
+        Self::from_node(Application(left_term, right_term))
     }
 
     pub fn new_church_num(denominator: u32) -> Self {
         use LambdaNode::*;
-        LambdaTree(Rc::new(ChurchNum(denominator)))
+        Self::from_node(ChurchNum(denominator))
+    }
+
+    pub fn new_let(var: String, value: Self, body: Self) -> Self {
+        use LambdaNode::*;
+        Self::from_node(Let(var, value, body))
+    }
+
+    /// Build the list `[items...]` from `CONS` and `NIL` (see [`stdlib::literal_encoding`])
+    pub fn new_list(items: Vec<Self>) -> Self {
+        let cons = Self::new_named(stdlib::literal_encoding("CONS"));
+        let nil = Self::new_named(stdlib::literal_encoding("NIL"));
+        items.into_iter().rev().fold(nil, |list, item| {
+            Self::new_application(Self::new_application(cons.clone(), item), list)
+        })
+    }
+
+    /// Build the pair `<first, second>` from `PAIR` (see [`stdlib::literal_encoding`])
+    pub fn new_pair(first: Self, second: Self) -> Self {
+        let pair = Self::new_named(stdlib::literal_encoding("PAIR"));
+        Self::new_application(Self::new_application(pair, first), second)
+    }
+
+    /// Build a string as list of the Church numerals for its character codes
+    pub fn new_string(text: &str) -> Self {
+        Self::new_list(text.chars().map(|c| Self::unwrap_church_num(c as u32)).collect())
+    }
+
+    pub fn new_named(named: Shared<NamedTerm>) -> Self {
+        use LambdaNode::*;
+        Self::from_node(Named(named))
+    }
+
+    /// Build a `match` over `scrutinee`, each handler abstracts over the fields of its constructor
+    pub fn new_match(scrutinee: Self, arms: Vec<(String, usize, Self)>) -> Self {
+        use LambdaNode::*;
+        let mut terms = vec![scrutinee];
+        let arms = arms.into_iter()
+            .map(|(constructor, nfields, handler)| {
+                terms.push(handler);
+                (constructor, nfields)
+            })
+            .collect();
+        Self::from_node(Match(arms, terms))
     }
 
     pub fn new_macro(m: Macro, terms: Vec<Self>) -> Self {
         use LambdaNode::*;
-        LambdaTree(Rc::new(Macro(m, terms)))
+        Self::from_node(Macro(m, terms))
     }
 
     pub fn new_variable(name: String) -> Self {
         use LambdaNode::*;
-        LambdaTree(Rc::new(Variable(name)))
+        Self::from_node(Variable(name))
     }
 
     pub fn apply_macros<E: Environment>(&self, interpreter: &mut Interpreter<E>) -> LashResult<Self> {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(var, term) => Ok(Self::new_abstraction(var.to_string(), term.apply_macros(interpreter)?)),
-            Application(left_term, right_term)
-                => Ok(Self::new_application(left_term.apply_macros(interpreter)?, right_term.apply_macros(interpreter)?)),
-            Variable(_) => Ok(self.clone()),
-            Macro(m, terms) => {
-                let time_start = interpreter.env().now();
-                let terms = terms.iter().map(|m| m.apply_macros(interpreter)).collect::<Vec<LashResult<LambdaTree>>>();
-                let duration = interpreter.env().elapsed(time_start);
-                if let Some(e) = terms.iter().find(|r| r.is_err()) {
-                    e.clone()
-                } else {
-                    let terms = terms.iter().flatten().cloned().collect();
-                    m.apply(interpreter, terms, duration)
-                }
-            } ,
-            Named(_) | ChurchNum(_) => Ok(self.clone()),
+
+        enum Work<I> {
+            Step(Step),
+            Apply(crate::r#macro::Macro, usize, I, Option<Span>),
         }
-    }
 
-    fn contains_free_variable(&self, variable: &str) -> bool {
-        use LambdaNode::*;
-        match self.node() {
-            Abstraction(var, term) => if var == variable { false } else { term.contains_free_variable(variable) },
-            Application(left_term, right_term) => left_term.contains_free_variable(variable) || right_term.contains_free_variable(variable),
-            Variable(var) => var == variable,
-            Macro(_, terms) => terms.iter().any(|t| t.contains_free_variable(variable)),
-            Named(named) => named.term().contains_free_variable(variable),
-            ChurchNum(_) => false,
+        let mut work = vec![Work::Step(Step::Visit(self.clone()))];
+        let mut steps = Vec::new();
+        let mut results = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Step(Step::Visit(term)) => match term.node() {
+                    Macro(m, terms) => {
+                        let time_start = interpreter.env().now();
+                        work.push(Work::Apply(*m, terms.len(), time_start, term.span()));
+                        work.extend(terms.iter().rev().cloned().map(|t| Work::Step(Step::Visit(t))));
+                    },
+                    _ => if Step::descend(&term, &mut steps) {
+                        work.extend(steps.drain(..).map(Work::Step));
+                    } else {
+                        results.push(term);
+                    },
+                },
+                Work::Step(step) => if let Some(term) = step.assemble(&mut results) {
+                    results.push(term);
+                },
+                Work::Apply(m, nterms, time_start, span) => {
+                    let duration = interpreter.env().elapsed(time_start);
+                    let terms = results.split_off(results.len() - nterms);
+                    results.push(m.apply(interpreter, terms, duration).map_err(|e| e.with_span(span))?);
+                },
+            }
         }
+        Ok(results.pop().unwrap())
     }
 
     pub fn fmt_with_parenthesis(&self, left_of_appl: bool) -> String {
@@ -117,16 +515,181 @@ impl LambdaTree {
         }
     }
 
-    pub fn has_church_nums(&self) -> bool {
+    /// Collect all variables that occur freely in this term or the named terms it references
+    pub fn free_variables(&self) -> BTreeSet<String> {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(_, term) => term.has_church_nums(),
-            Application(left_term, right_term) => left_term.has_church_nums() || right_term.has_church_nums(),
-            Variable(_) => false,
-            Macro(_, terms) => terms.iter().any(|t| t.has_church_nums()),
-            Named(named) => named.term().has_church_nums(),
-            ChurchNum(_) => true,
+
+        enum Work {
+            Visit(LambdaTree),
+            Bind(String),
+            Unbind(String),
+        }
+
+        let mut free = BTreeSet::new();
+        let mut visited_named = BTreeSet::new();
+        // named terms are checked on their own, without the binders around their occurrence
+        let mut roots = vec![self.clone()];
+        while let Some(root) = roots.pop() {
+            let mut bound: BTreeMap<String, usize> = BTreeMap::new();
+            let mut work = vec![Work::Visit(root)];
+            while let Some(item) = work.pop() {
+                match item {
+                    Work::Visit(term) => match term.node() {
+                        Abstraction(var, inner) => {
+                            *bound.entry(var.clone()).or_default() += 1;
+                            work.push(Work::Unbind(var.clone()));
+                            work.push(Work::Visit(inner.clone()));
+                        },
+                        Let(var, value, body) => {
+                            work.push(Work::Unbind(var.clone()));
+                            work.push(Work::Visit(body.clone()));
+                            work.push(Work::Bind(var.clone()));
+                            work.push(Work::Visit(value.clone()));
+                        },
+                        Application(left_term, right_term) => {
+                            work.push(Work::Visit(right_term.clone()));
+                            work.push(Work::Visit(left_term.clone()));
+                        },
+                        Variable(var) => if !bound.contains_key(var) {
+                            free.insert(var.clone());
+                        },
+                        Macro(_, terms) | Match(_, terms) => work.extend(terms.iter().cloned().map(Work::Visit)),
+                        Named(named) => if visited_named.insert(Shared::as_ptr(named) as usize) {
+                            roots.push(named.term());
+                        },
+                        ChurchNum(_) => (),
+                    },
+                    Work::Bind(var) => *bound.entry(var).or_default() += 1,
+                    Work::Unbind(var) => unbind(&mut bound, &var),
+                }
+            }
         }
+        free
+    }
+
+    /// Find the variables that occur freely in this term, along with the position they first occur at
+    ///
+    /// Unlike [`LambdaTree::free_variables`] this does not look into named terms.
+    pub fn free_occurrences(&self) -> BTreeMap<String, Option<Span>> {
+        use LambdaNode::*;
+        let mut free = BTreeMap::new();
+        let mut bound: BTreeMap<String, usize> = BTreeMap::new();
+        let mut steps = vec![Step::Visit(self.clone())];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => match term.node() {
+                    Variable(var) => if !bound.contains_key(var) {
+                        free.entry(var.clone()).or_insert(term.span());
+                    },
+                    Abstraction(var, _) => {
+                        *bound.entry(var.clone()).or_default() += 1;
+                        Step::descend(&term, &mut steps);
+                    },
+                    _ => { Step::descend(&term, &mut steps); },
+                },
+                Step::Bind(var) => *bound.entry(var).or_default() += 1,
+                Step::Abstraction(var) | Step::Let(var) => unbind(&mut bound, &var),
+                _ => (),
+            }
+        }
+        free
+    }
+
+    /// Names of the named terms this term refers to, without looking into them
+    pub fn references(&self) -> BTreeSet<String> {
+        use LambdaNode::*;
+        let mut references = BTreeSet::new();
+        let mut stack = vec![self.clone()];
+        while let Some(term) = stack.pop() {
+            match term.node() {
+                Abstraction(_, inner) => stack.push(inner.clone()),
+                Application(left_term, right_term) | Let(_, left_term, right_term) => {
+                    stack.push(left_term.clone());
+                    stack.push(right_term.clone());
+                },
+                Macro(_, terms) | Match(_, terms) => stack.extend(terms.iter().cloned()),
+                Named(named) => { references.insert(named.name().to_owned()); },
+                Variable(_) | ChurchNum(_) => (),
+            }
+        }
+        references
+    }
+
+    /// Names of the named terms this term depends on, directly or through other named terms
+    ///
+    /// For a named term itself, these are the dependencies of its definition.
+    pub fn dependencies(&self) -> BTreeSet<String> {
+        use LambdaNode::*;
+        let mut dependencies = BTreeSet::new();
+        let mut stack = match self.node() {
+            Named(named) => vec![named.unfold()],
+            _ => vec![self.clone()],
+        };
+        while let Some(term) = stack.pop() {
+            match term.node() {
+                Abstraction(_, inner) => stack.push(inner.clone()),
+                Application(left_term, right_term) | Let(_, left_term, right_term) => {
+                    stack.push(left_term.clone());
+                    stack.push(right_term.clone());
+                },
+                Macro(_, terms) | Match(_, terms) => stack.extend(terms.iter().cloned()),
+                Named(named) => if dependencies.insert(named.name().to_owned()) {
+                    stack.push(named.unfold());
+                },
+                Variable(_) | ChurchNum(_) => (),
+            }
+        }
+        dependencies
+    }
+
+    /// Collect the variables bound by abstractions and `let`s in this term, along with their positions
+    pub fn binders(&self) -> Vec<(String, Option<Span>)> {
+        use LambdaNode::*;
+        let mut binders = Vec::new();
+        let mut stack = vec![self.clone()];
+        while let Some(term) = stack.pop() {
+            match term.node() {
+                Abstraction(var, inner) => {
+                    binders.push((var.clone(), term.span()));
+                    stack.push(inner.clone());
+                },
+                Let(var, value, body) => {
+                    binders.push((var.clone(), term.span()));
+                    stack.push(body.clone());
+                    stack.push(value.clone());
+                },
+                Application(left_term, right_term) => {
+                    stack.push(right_term.clone());
+                    stack.push(left_term.clone());
+                },
+                Macro(_, terms) | Match(_, terms) => stack.extend(terms.iter().rev().cloned()),
+                Named(_) | Variable(_) | ChurchNum(_) => (),
+            }
+        }
+        binders
+    }
+
+    /// Find a Church numeral in this term or the named terms it references
+    pub fn find_church_num(&self) -> Option<LambdaTree> {
+        use LambdaNode::*;
+        let mut visited_named = BTreeSet::new();
+        let mut stack = vec![self.clone()];
+        while let Some(term) = stack.pop() {
+            match term.node() {
+                Abstraction(_, inner) => stack.push(inner.clone()),
+                Application(left_term, right_term) | Let(_, left_term, right_term) => {
+                    stack.push(left_term.clone());
+                    stack.push(right_term.clone());
+                },
+                Variable(_) => (),
+                Macro(_, terms) | Match(_, terms) => stack.extend(terms.iter().cloned()),
+                Named(named) => if visited_named.insert(Shared::as_ptr(named) as usize) {
+                    stack.push(named.term());
+                },
+                ChurchNum(_) => return Some(term),
+            }
+        }
+        None
     }
 
     pub fn is_abstraction(&self) -> bool {
@@ -154,98 +717,327 @@ impl LambdaTree {
         matches!(self.node(), Variable(..))
     }
 
+    /// Check whether both terms share the same node, regardless of their positions
+    ///
+    /// For terms interned in the same [`TermTable`](crate::hashcons::TermTable) this is
+    /// equivalent to structural equality.
+    pub fn ptr_eq(&self, other: &LambdaTree) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Value of this term as Church numeral, if it is one
+    fn church_num_value(&self) -> Option<u32> {
+        use LambdaNode::*;
+        if let ChurchNum(d) = self.node() {
+            return Some(*d);
+        }
+        let Abstraction(f, inner) = self.node() else { return None };
+        let Abstraction(x, body) = inner.node() else { return None };
+        let mut body = body;
+        if f == x {
+            return None;
+        }
+        let mut value = 0;
+        loop {
+            match body.node() {
+                Variable(v) if v == x => return Some(value),
+                Application(left_term, right_term) if matches!(left_term.node(), Variable(v) if v == f) => {
+                    value += 1;
+                    body = right_term;
+                },
+                _ => return None,
+            }
+        }
+    }
+
+    /// Recognize the normal forms of list, pair and string literals
+    fn read_back(&self, strings: bool) -> Option<Sugar<'_>> {
+        use LambdaNode::*;
+        let occurs = |term: &LambdaTree, vars: &[&String]| {
+            let free = term.free_variables();
+            vars.iter().any(|v| free.contains(v.as_str()))
+        };
+
+        let Abstraction(outer, inner) = self.node() else { return None };
+        // \z . z x y
+        if let Application(left_term, second) = inner.node() {
+            if let Application(selector, first) = left_term.node() {
+                if matches!(selector.node(), Variable(v) if v == outer)
+                        && !occurs(first, &[outer]) && !occurs(second, &[outer]) {
+                    return Some(Sugar::Pair(first, second));
+                }
+            }
+        }
+
+        // \c . \n . c x1 (c x2 ... n), the empty list is indistinguishable from FALSE
+        let Abstraction(nil, rest) = inner.node() else { return None };
+        let mut rest = rest;
+        if outer == nil {
+            return None;
+        }
+        let mut items = Vec::new();
+        loop {
+            match rest.node() {
+                Variable(v) if v == nil && !items.is_empty() => break,
+                Application(left_term, tail) => match left_term.node() {
+                    Application(cons, item) if matches!(cons.node(), Variable(v) if v == outer)
+                            && !occurs(item, &[outer, nil]) => {
+                        items.push(item);
+                        rest = tail;
+                    },
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+
+        if !strings {
+            return Some(Sugar::List(items));
+        }
+        let text: Option<String> = items.iter()
+            .map(|item| item.church_num_value().and_then(char::from_u32))
+            .map(|c| c.filter(|c| (c.is_ascii_graphic() || *c == ' ') && *c != '"'))
+            .collect();
+        match text {
+            Some(text) => Some(Sugar::String(text)),
+            None => Some(Sugar::List(items)),
+        }
+    }
+
     pub fn needs_parenthesis(&self, left_of_appl: bool) -> bool {
         !(self.is_named() || self.is_variable() || self.is_church_num() || (left_of_appl && self.is_application()))
     }
 
     pub fn node(&self) -> &LambdaNode {
-        let LambdaTree(item) = self;
-        item.as_ref()
+        &self.0.node
     }
 
-    pub fn set_named_terms(&self, named_terms: &BTreeMap<String, Rc<NamedTerm>>) -> Self {
-        self.set_named_terms_helper(named_terms, &mut Vec::new())
+    pub fn set_named_terms(&self, named_terms: &BTreeMap<String, Shared<NamedTerm>>) -> Self {
+        use LambdaNode::*;
+        let mut bound_vars: BTreeMap<String, usize> = BTreeMap::new();
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => match term.node() {
+                    Variable(name) if !bound_vars.contains_key(name) => match named_terms.get(name) {
+                        Some(named) => results.push(Self::new_named(named.clone()).at_optional(term.span())),
+                        None => results.push(term),
+                    },
+                    Abstraction(var, _) => {
+                        *bound_vars.entry(var.clone()).or_default() += 1;
+                        Step::descend(&term, &mut steps);
+                    },
+                    _ => if !Step::descend(&term, &mut steps) {
+                        results.push(term);
+                    },
+                },
+                step => {
+                    match &step {
+                        Step::Bind(var) => *bound_vars.entry(var.clone()).or_default() += 1,
+                        Step::Abstraction(var) | Step::Let(var) => unbind(&mut bound_vars, var),
+                        _ => (),
+                    }
+                    if let Some(term) = step.assemble(&mut results) {
+                        results.push(term);
+                    }
+                },
+            }
+        }
+        results.pop().unwrap()
     }
 
-    fn set_named_terms_helper(&self, named_terms: &BTreeMap<String, Rc<NamedTerm>>, bound_vars: &mut Vec<String>) -> Self {
+    /// Turn references to `named_terms` back into variables, so they can be linked again later
+    ///
+    /// References in the scope of a binder of the same name are kept, as they would be captured.
+    pub fn unlink(&self, named_terms: &BTreeMap<String, Shared<NamedTerm>>) -> Self {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(var, term) => {
-                bound_vars.push(var.to_owned());
-                let new_term = term.set_named_terms_helper(named_terms, bound_vars);
-                bound_vars.pop();
-                LambdaTree(Rc::new(Abstraction(var.clone(), new_term)))
-            },
-            Application(left_term, right_term) => {
-                let new_left_term = left_term.set_named_terms_helper(named_terms, bound_vars);
-                let new_right_term = right_term.set_named_terms_helper(named_terms, bound_vars);
-                LambdaTree(Rc::new(Application(new_left_term, new_right_term)))
-            },
-            Variable(name) => {
-                if bound_vars.contains(name) {
-                    self.clone()
-                } else if let Some(named) = named_terms.get(name) {
-                    LambdaTree(Rc::new(Named(named.clone())))
-                } else {
-                    self.clone()
-                }
-            },
-            Macro(m, terms) => Self::new_macro(*m, terms.iter()
-                                               .map(|t| t.set_named_terms_helper(named_terms, bound_vars)).collect()),
-            Named(_) | ChurchNum(_) => self.clone(),
+        let mut bound_vars: BTreeMap<String, usize> = BTreeMap::new();
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => match term.node() {
+                    Named(named) if !bound_vars.contains_key(named.name())
+                            && named_terms.get(named.name()).is_some_and(|n| Shared::ptr_eq(n, named)) =>
+                        results.push(Self::new_variable(named.name().to_owned()).at_optional(term.span())),
+                    Abstraction(var, _) => {
+                        *bound_vars.entry(var.clone()).or_default() += 1;
+                        Step::descend(&term, &mut steps);
+                    },
+                    _ => if !Step::descend(&term, &mut steps) {
+                        results.push(term);
+                    },
+                },
+                step => {
+                    match &step {
+                        Step::Bind(var) => *bound_vars.entry(var.clone()).or_default() += 1,
+                        Step::Abstraction(var) | Step::Let(var) => unbind(&mut bound_vars, var),
+                        _ => (),
+                    }
+                    if let Some(term) = step.assemble(&mut results) {
+                        results.push(term);
+                    }
+                },
+            }
+        }
+        results.pop().unwrap()
+    }
+
+    /// Replace the references to named terms for which `replace` returns another named term
+    pub fn replace_named<F>(&self, mut replace: F) -> Self
+            where F: FnMut(&Shared<NamedTerm>) -> Option<Shared<NamedTerm>> {
+        use LambdaNode::*;
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => match term.node() {
+                    Named(named) => match replace(named) {
+                        Some(named) => results.push(Self::new_named(named).at_optional(term.span())),
+                        None => results.push(term),
+                    },
+                    _ => if !Step::descend(&term, &mut steps) {
+                        results.push(term);
+                    },
+                },
+                step => if let Some(term) = step.assemble(&mut results) {
+                    results.push(term);
+                },
+            }
+        }
+        results.pop().unwrap()
+    }
+
+    /// Replace all `match` expressions with the terms built by `desugar`
+    ///
+    /// `desugar` gets the arms of a match along with the desugared scrutinee and handlers.
+    pub fn desugar_matches<F>(&self, mut desugar: F) -> LashResult<Self>
+            where F: FnMut(&[(String, usize)], Vec<LambdaTree>) -> LashResult<LambdaTree> {
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => if !Step::descend(&term, &mut steps) {
+                    results.push(term);
+                },
+                Step::Match(arms, span) => {
+                    let terms = results.split_off(results.len() - arms.len() - 1);
+                    results.push(desugar(&arms, terms).map_err(|e| e.with_span(span))?);
+                },
+                step => if let Some(term) = step.assemble(&mut results) {
+                    results.push(term);
+                },
+            }
         }
+        Ok(results.pop().unwrap())
     }
 
     /// Resolve all named terms
     pub fn resolve(&self) -> Self {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(var, inner_term) => Self::new_abstraction(var.clone(), inner_term.resolve()),
-            Application(left_term, right_term) => Self::new_application(left_term.resolve(), right_term.resolve()),
-            Macro(m, terms) => Self::new_macro(*m, terms.iter().map(|t| t.resolve()).collect()),
-            Variable(_) => self.clone(),
-            Named(term) => term.term().resolve(),
-            ChurchNum(d) => Self::unwrap_church_num(*d),
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(term) => match term.node() {
+                    Named(named) => steps.push(Step::Visit(named.term())),
+                    ChurchNum(d) => results.push(Self::unwrap_church_num(*d)),
+                    _ => if !Step::descend(&term, &mut steps) {
+                        results.push(term);
+                    },
+                },
+                step => if let Some(term) = step.assemble(&mut results) {
+                    results.push(term);
+                },
+            }
         }
+        results.pop().unwrap()
+    }
+
+    /// Number of nodes of this term, with named terms and numerals counting as one
+    ///
+    /// Shared subterms are counted once per occurrence. The size is computed when a node is built.
+    pub fn size(&self) -> usize {
+        self.0.size
     }
 
     pub fn substitute(&self, name: &str, term: LambdaTree) -> Self {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(var, inner_term) => {
-                if var == name {
-                    self.clone()
-                } else {
-                    // avoid capturing free variables
-                    if term.contains_free_variable(var) {
-                        let mut fresh_var = var.to_owned();
-                        while term.contains_free_variable(&fresh_var) {
-                            fresh_var = format!("{}'", fresh_var);
+        let free = Rc::new(term.free_variables());
+        // substitutions in effect for the current subterm, renamed binders are added as they are encountered
+        let mut scopes = vec![vec![Substitution { name: name.to_owned(), term, free }]];
+        // scopes for the bodies of `let`, which are entered only after the bound value is done
+        let mut pending_scopes = Vec::new();
+        let mut steps = vec![Step::Visit(self.clone())];
+        let mut results = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(current) => match current.node() {
+                    Abstraction(var, inner_term) => {
+                        let (scope, binder) = Self::enter_binder(scopes.last().unwrap(), var);
+                        if scope.is_empty() {
+                            results.push(current.clone());
+                            continue;
                         }
-                        let new_inner = inner_term
-                            .substitute(var, Self::new_variable(fresh_var.clone()))
-                            .substitute(name, term.clone());
-                        Self::new_abstraction(fresh_var, new_inner)
-                    } else {
-                        Self::new_abstraction(var.clone(), inner_term.substitute(name, term.clone()))
+                        scopes.push(scope);
+                        steps.push(Step::Abstraction(binder));
+                        steps.push(Step::Visit(inner_term.clone()));
+                    },
+                    Let(var, value, body) => {
+                        let (scope, binder) = Self::enter_binder(scopes.last().unwrap(), var);
+                        pending_scopes.push(scope);
+                        steps.push(Step::Let(binder));
+                        steps.push(Step::Visit(body.clone()));
+                        steps.push(Step::Bind(var.clone()));
+                        steps.push(Step::Visit(value.clone()));
+                    },
+                    Variable(var_name) => match scopes.last().unwrap().iter().find(|s| &s.name == var_name) {
+                        Some(substitution) => results.push(substitution.term.clone()),
+                        None => results.push(current.clone()),
+                    },
+                    _ => if !Step::descend(&current, &mut steps) {
+                        results.push(current);
+                    },
+                },
+                step => {
+                    match step {
+                        Step::Bind(_) => scopes.push(pending_scopes.pop().unwrap()),
+                        Step::Abstraction(_) | Step::Let(_) => { scopes.pop(); },
+                        _ => (),
                     }
-                }
-            },
-            Application(left_term, right_term) => {
-                let new_left_term = left_term.substitute(name, term.clone());
-                let new_right_term = right_term.substitute(name, term);
-                LambdaTree(Rc::new(Application(new_left_term, new_right_term)))
-            },
-            Variable(var_name) => {
-                if var_name == name {
-                    term.clone()
-                } else {
-                    self.clone()
-                }
-            },
-            Macro(m, terms) => Self::new_macro(*m, terms.iter().map(|t| t.substitute(name, term.clone())).collect()),
-            Named(_) | ChurchNum(_) => self.clone(),
+                    if let Some(term) = step.assemble(&mut results) {
+                        results.push(term);
+                    }
+                },
+            }
         }
+        results.pop().unwrap()
+    }
+
+    /// Compute the substitutions in effect below a binder for `var` and the name the binder gets
+    ///
+    /// The binder is renamed if it would capture a free variable of one of the substituted terms.
+    fn enter_binder(scope: &[Substitution], var: &str) -> (Vec<Substitution>, String) {
+        let mut scope: Vec<_> = scope.iter()
+            .filter(|s| s.name != var)
+            .cloned()
+            .collect();
+
+        // avoid capturing free variables
+        let captures = |v: &str, scope: &[Substitution]| scope.iter().any(|s| s.free.contains(v));
+        let mut binder = var.to_owned();
+        if captures(var, &scope) {
+            while captures(&binder, &scope) {
+                binder = format!("{}'", binder);
+            }
+            scope.push(Substitution {
+                name: var.to_owned(),
+                term: Self::new_variable(binder.clone()),
+                free: Rc::new(BTreeSet::from([binder.clone()])),
+            });
+        }
+        (scope, binder)
     }
 
     pub fn unwrap_church_num(denominator: u32) ->  Self {
@@ -255,34 +1047,190 @@ impl LambdaTree {
         }
         Self::new_abstraction("f".to_owned(), Self::new_abstraction("x".to_string(), inner))
     }
+
+    /// Move the children out of this node if it is not shared, so they can be dropped one by one
+    fn take_children(&mut self, stack: &mut Vec<LambdaTree>) {
+        use LambdaNode::*;
+        if Shared::strong_count(&self.0) != 1 {
+            return;
+        }
+        // moves the node out of the allocation if the term table still refers to it
+        match mem::replace(&mut Shared::make_mut(&mut self.0).node, ChurchNum(0)) {
+            Abstraction(_, term) => stack.push(term),
+            Application(left_term, right_term) | Let(_, left_term, right_term) => {
+                stack.push(left_term);
+                stack.push(right_term);
+            },
+            Macro(_, terms) | Match(_, terms) => stack.extend(terms),
+            Named(named) => if let Ok(named) = Shared::try_unwrap(named) {
+                stack.push(named.term);
+            },
+            Variable(_) | ChurchNum(_) => (),
+        }
+    }
+}
+
+fn unbind(bound: &mut BTreeMap<String, usize>, var: &str) {
+    if let Some(count) = bound.get_mut(var) {
+        *count -= 1;
+        if *count == 0 {
+            bound.remove(var);
+        }
+    }
+}
+
+impl Drop for LambdaTree {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+        while let Some(mut term) = stack.pop() {
+            term.take_children(&mut stack);
+        }
+    }
+}
+
+impl ReadBack {
+    /// Format `value` with the flags that select this read-back
+    pub fn format(self, value: &impl Display) -> String {
+        match self {
+            ReadBack::Off => format!("{}", value),
+            ReadBack::Literals => format!("{:#}", value),
+            ReadBack::Strings => format!("{:+#}", value),
+        }
+    }
+}
+
+impl FromStr for ReadBack {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "false" => Ok(ReadBack::Off),
+            "true" => Ok(ReadBack::Literals),
+            "strings" => Ok(ReadBack::Strings),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ReadBack {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ReadBack::Off => write!(f, "false"),
+            ReadBack::Literals => write!(f, "true"),
+            ReadBack::Strings => write!(f, "strings"),
+        }
+    }
 }
 
 impl Display for LambdaTree {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use LambdaNode::*;
-        match self.node() {
-            Abstraction(var_name, term) => {
-                write!(f, "\\{} . ", var_name)?;
-                term.fmt(f)
-            },
-            Application(term1, term2) => {
-                write!(f, "{} {}", term1.fmt_with_parenthesis(true), term2.fmt_with_parenthesis(false))
-            },
-            Variable(name) => write!(f, "{}", name),
-            Macro(m, terms) => {
-                write!(f, "!{} ", m)?;
-                for term in terms {
-                    if let Variable(name) = term.node() {
-                        write!(f, "{}", name)?;
-                    } else {
-                        write!(f, "({})", term)?;
+
+        enum Piece<'a> {
+            Term(&'a LambdaTree),
+            Text(&'static str),
+            Owned(String),
+        }
+
+        // the alternate flag enables read-back of literals, together with `+` also of strings
+        let read_back = f.alternate();
+        let strings = f.sign_plus();
+        let mut pieces = vec![Piece::Term(self)];
+        while let Some(piece) = pieces.pop() {
+            let term = match piece {
+                Piece::Term(term) => term,
+                Piece::Text(text) => { f.write_str(text)?; continue },
+                Piece::Owned(text) => { f.write_str(&text)?; continue },
+            };
+            if read_back {
+                match term.read_back(strings) {
+                    Some(Sugar::List(items)) => {
+                        pieces.push(Piece::Text("]"));
+                        for (i, item) in items.into_iter().enumerate().rev() {
+                            pieces.push(Piece::Term(item));
+                            if i > 0 {
+                                pieces.push(Piece::Text(", "));
+                            }
+                        }
+                        f.write_str("[")?;
+                        continue;
+                    },
+                    Some(Sugar::Pair(first, second)) => {
+                        pieces.push(Piece::Text(">"));
+                        pieces.push(Piece::Term(second));
+                        pieces.push(Piece::Text(", "));
+                        pieces.push(Piece::Term(first));
+                        f.write_str("<")?;
+                        continue;
+                    },
+                    Some(Sugar::String(text)) => { write!(f, "\"{}\"", text)?; continue },
+                    None => (),
+                }
+            }
+            let needs_parenthesis = |term: &LambdaTree, left_of_appl| {
+                term.needs_parenthesis(left_of_appl) && !(read_back && term.read_back(strings).is_some())
+            };
+            match term.node() {
+                Abstraction(var_name, term) => {
+                    write!(f, "\\{} . ", var_name)?;
+                    pieces.push(Piece::Term(term));
+                },
+                Application(term1, term2) => {
+                    let parens2 = needs_parenthesis(term2, false);
+                    let parens1 = needs_parenthesis(term1, true);
+                    if parens2 { pieces.push(Piece::Text(")")); }
+                    pieces.push(Piece::Term(term2));
+                    pieces.push(Piece::Text(if parens2 { " (" } else { " " }));
+                    if parens1 { pieces.push(Piece::Text(")")); }
+                    pieces.push(Piece::Term(term1));
+                    if parens1 { pieces.push(Piece::Text("(")); }
+                },
+                Let(var, value, body) => {
+                    write!(f, "let {} := ", var)?;
+                    pieces.push(Piece::Term(body));
+                    pieces.push(Piece::Text(" in "));
+                    pieces.push(Piece::Term(value));
+                },
+                Variable(name) => write!(f, "{}", name)?,
+                Macro(m, terms) => {
+                    write!(f, "!{} ", m)?;
+                    for term in terms.iter().rev() {
+                        if term.is_variable() {
+                            pieces.push(Piece::Term(term));
+                        } else {
+                            pieces.push(Piece::Text(")"));
+                            pieces.push(Piece::Term(term));
+                            pieces.push(Piece::Text("("));
+                        }
                     }
                 }
-                Ok(())
+                Match(arms, terms) => {
+                    f.write_str("match ")?;
+                    for (i, ((constructor, nfields), handler)) in arms.iter().zip(&terms[1..]).enumerate().rev() {
+                        // the fields are the binders of the handler
+                        let mut body = handler;
+                        let mut fields = String::new();
+                        for _ in 0..*nfields {
+                            match body.node() {
+                                Abstraction(var, inner) => {
+                                    fields.push(' ');
+                                    fields.push_str(var);
+                                    body = inner;
+                                },
+                                _ => break,
+                            }
+                        }
+                        pieces.push(Piece::Term(body));
+                        pieces.push(Piece::Owned(format!("{}{}{} -> ", if i == 0 { "" } else { " | " }, constructor, fields)));
+                    }
+                    pieces.push(Piece::Text(" with "));
+                    pieces.push(Piece::Term(&terms[0]));
+                },
+                Named(named) => write!(f, "{}", named.name)?,
+                ChurchNum(d) => write!(f, "${}", d)?,
             }
-            Named(named) => write!(f, "{}", named.name),
-            ChurchNum(d) => write!(f, "${}", d),
         }
-
+        Ok(())
     }
 }
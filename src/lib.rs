@@ -2,15 +2,25 @@
 
 extern crate alloc;
 
+pub mod assertion;
+pub mod context;
+pub mod data;
 pub mod debruijn;
+pub mod dependencies;
+#[cfg(feature = "std")]
+pub mod doctest;
 pub mod environment;
 pub mod error;
+pub mod hashcons;
 pub mod interpreter;
 pub mod lambda;
 pub mod parsing;
 pub mod r#macro;
+pub mod settings;
 pub mod stdlib;
 pub mod strategy;
+#[cfg(feature = "std")]
+pub mod testing;
 pub mod typing;
 
 #[cfg(test)]
@@ -8,10 +8,12 @@ use core::str::FromStr;
 use core::time::Duration;
 
 use crate::debruijn::DeBruijnNode;
+use crate::dependencies::write_names;
 use crate::environment::Environment;
 use crate::error::{LashError, LashResult};
 use crate::interpreter::Interpreter;
 use crate::lambda::*;
+use crate::strategy::Strategy;
 use crate::typing;
 
 // Improved version of
@@ -38,18 +40,21 @@ macro_rules! count {
 }
 
 enum_with_values! {
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
     #[cfg_attr(feature = "std", derive(clap::ValueEnum))]
     #[cfg_attr(feature = "std", clap(rename_all = "lower"))]
     vis pub enum Macro {
         AlphaEq,
         CNormalize,
+        Compare,
         DeBruijn,
         Debug,
+        Deps,
         Macros,
         Normalize,
         Reduce,
         Resolve,
+        Stats,
         Time,
         Type,
         VNormalize,
@@ -79,17 +84,20 @@ impl Macro {
         }
 
         let strategy = interpreter.strategy();
-        let mut stdout = interpreter.env().stdout();
+        let interned = interpreter.term_table().live_terms();
+        let limits = interpreter.limits();
+        let read_back = interpreter.read_back();
+        let env = interpreter.env();
         let term = match self {
             AlphaEq => if terms[0].alpha_eq(&terms[1]) {
-                writeln!(stdout, "Terms are alpha equivalent")?;
+                writeln!(env.stdout(), "Terms are alpha equivalent")?;
                 LambdaTree::new_abstraction("x".to_owned(),
                     LambdaTree::new_abstraction("y".to_owned(),
                         LambdaTree::new_variable("x".to_owned())
                     )
                 )
             } else {
-                writeln!(stdout, "Terms are NOT alpha equivalent")?;
+                writeln!(env.stdout(), "Terms are NOT alpha equivalent")?;
                 LambdaTree::new_abstraction("x".to_owned(),
                     LambdaTree::new_abstraction("y".to_owned(),
                         LambdaTree::new_variable("y".to_owned())
@@ -97,43 +105,73 @@ impl Macro {
                 )
             },
             CNormalize => {
-                let (term, count) = strategy.normalize(terms[0].clone(), false, &mut stdout);
-                writeln!(stdout, "Number of reductions: {}", count)?;
+                let (term, count) = strategy.normalize(terms[0].clone(), false, &limits, env)?;
+                writeln!(env.stdout(), "Number of reductions: {}", count)?;
                 term
             },
+            Compare => {
+                let mut normal_forms = Vec::new();
+                let mut result = terms[0].clone();
+                for (s, outcome) in Strategy::compare(terms[0].clone(), &limits, env) {
+                    match outcome {
+                        Ok((term, count)) => {
+                            writeln!(env.stdout(), "{: <12} {} reductions", s.to_string(), count)?;
+                            if s == strategy {
+                                result = term.clone();
+                            }
+                            normal_forms.push(term);
+                        },
+                        Err(e) => writeln!(env.stdout(), "{: <12} {}", s.to_string(), e)?,
+                    }
+                }
+                if normal_forms.windows(2).all(|w| w[0].alpha_eq(&w[1])) {
+                    writeln!(env.stdout(), "Results are alpha equivalent")?;
+                } else {
+                    writeln!(env.stdout(), "Results are NOT alpha equivalent")?;
+                }
+                result
+            },
             DeBruijn => {
-                writeln!(stdout, "{}", DeBruijnNode::from(terms[0].clone()))?;
+                writeln!(env.stdout(), "{}", DeBruijnNode::from(terms[0].clone()))?;
                 terms[0].clone()
             },
             Debug => {
-                writeln!(stdout, "{}", terms[0].clone())?;
+                writeln!(env.stdout(), "{}", read_back.format(&terms[0]))?;
                 terms[0].clone()
             },
-            Macros => { Self::print_all(&mut stdout)?; LambdaTree::new_macro(self, terms) },
-            Normalize => strategy.normalize(terms[0].clone(), false, &mut stdout).0,
-            Reduce => if let Some(reduced) = strategy.reduce(terms[0].clone(), false, &mut stdout) {
+            Deps => {
+                write_names(env.stdout(), "Dependencies", "No dependencies", &terms[0].dependencies())?;
+                terms[0].clone()
+            },
+            Macros => { Self::print_all(env.stdout())?; LambdaTree::new_macro(self, terms) },
+            Normalize => strategy.normalize(terms[0].clone(), false, &limits, env)?.0,
+            Reduce => if let Some(reduced) = strategy.reduce(terms[0].clone(), false, env.stdout()) {
                 reduced
             } else {
                 terms[0].clone()
             },
             Resolve => terms[0].resolve(),
+            Stats => {
+                writeln!(env.stdout(), "Interned terms: {}", interned)?;
+                terms[0].clone()
+            },
             Time => {
                 #[cfg(feature = "std")]
-                writeln!(stdout, "Time elapsed: {}", humantime::format_duration(Duration::from_millis(duration.as_millis() as u64)))?;
+                writeln!(env.stdout(), "Time elapsed: {}", humantime::format_duration(Duration::from_millis(duration.as_millis() as u64)))?;
                 #[cfg(not(feature = "std"))]
-                writeln!(stdout, "Time elapsed: {}ms", duration.as_millis() as u64)?;
+                writeln!(env.stdout(), "Time elapsed: {}ms", duration.as_millis() as u64)?;
                 terms[0].clone()
             },
-            VNormalize => strategy.normalize(terms[0].clone(), true, &mut stdout).0,
-            VReduce => if let Some(reduced) = strategy.reduce(terms[0].clone(), true, &mut stdout) {
+            VNormalize => strategy.normalize(terms[0].clone(), true, &limits, env)?.0,
+            VReduce => if let Some(reduced) = strategy.reduce(terms[0].clone(), true, env.stdout()) {
                 reduced
             } else {
                 terms[0].clone()
             },
             Type => {
                 match typing::infer(terms[0].clone()) {
-                    Ok(t) => writeln!(stdout, "Infered type: {}", t)?,
-                    Err(e) => writeln!(stdout, "Cannot infer type: {}", e)?,
+                    Ok(t) => writeln!(env.stdout(), "Infered type: {}", t)?,
+                    Err(e) => writeln!(env.stdout(), "Cannot infer type: {}", e)?,
                 }
                 terms[0].clone()
             },
@@ -147,12 +185,15 @@ impl Macro {
         match self {
             AlphaEq => "check for alpha equivalence and return Church-encoded boolean",
             CNormalize => "normalize and show number of reductions performed",
+            Compare => "normalize with all strategies and compare the results",
             DeBruijn => "print out DeBruijn form",
             Debug => "print out current term (useful in non-interactive mode)",
+            Deps => "print the named terms the given term depends on",
             Macros => "print available macros",
             Normalize => "normalize the given term",
             Reduce => "reduce the given term",
             Resolve => "resolve all named terms",
+            Stats => "print interpreter statistics and return the given term",
             Time => "time the execution of the macros contained inside the term",
             Type => "try to infer a type for the given term",
             VNormalize => "visually normalize the given term",
@@ -165,12 +206,15 @@ impl Macro {
         match self {
             AlphaEq => 2,
             CNormalize => 1,
+            Compare => 1,
             DeBruijn => 1,
             Debug => 1,
+            Deps => 1,
             Macros => 0,
             Normalize => 1,
             Reduce => 1,
             Resolve => 1,
+            Stats => 1,
             Time => 1,
             Type => 1,
             VNormalize => 1,
@@ -186,12 +230,15 @@ impl Display for Macro {
         let name = match self {
             AlphaEq => "alphaeq",
             CNormalize => "cnormalize",
+            Compare => "compare",
             DeBruijn => "debruijn",
             Debug => "debug",
+            Deps => "deps",
             Macros => "macros",
             Normalize => "normalize",
             Reduce => "reduce",
             Resolve => "resolve",
+            Stats => "stats",
             Time => "time",
             VNormalize => "vnormalize",
             VReduce => "vreduce",
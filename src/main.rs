@@ -1,21 +1,29 @@
-use clap::Parser;
-use environment::StdEnvironment;
+use clap::{Parser, Subcommand};
+use environment::{CaptureEnvironment, StdEnvironment};
 use interpreter::Interpreter;
 use r#macro::Macro;
 use strategy::Strategy;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 
+mod assertion;
+mod context;
+mod data;
 mod debruijn;
+mod dependencies;
+mod doctest;
 mod environment;
 mod error;
+mod hashcons;
 mod interactive;
 mod interpreter;
 mod lambda;
 mod parsing;
 mod r#macro;
+mod settings;
 mod stdlib;
 mod strategy;
+mod testing;
 mod typing;
 
 #[cfg(test)]
@@ -30,12 +38,19 @@ const DOCS_URL: &str = "https://jzbor.de/lash";
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Evaluate lambda shell file
     #[clap()]
     file: Option<PathBuf>,
 
+    /// Compare the output of the file with the expected output stored in this file
+    #[clap(long, value_name = "EXPECTED", requires = "file")]
+    check_output: Option<PathBuf>,
+
     /// Set initial strategy
-    #[clap(long, value_enum, default_value_t = Strategy::Normal)]
+    #[clap(long, global = true, value_enum, default_value_t = Strategy::Normal)]
     strategy: Strategy,
 
     /// Print available macros and exit
@@ -43,14 +58,39 @@ struct Args {
     print_macros: bool,
 
     /// Disable standard environment and church numerals
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     strict: bool,
 
+    /// Change a setting like `@set` does
+    #[clap(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_setting)]
+    settings: Vec<(String, String)>,
+
     /// Open documentation in the browser
     #[clap(long)]
     docs: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Run .lsh files as tests, which fail on errors like failed assertions
+    Test {
+        /// Files or directories to search for .lsh files
+        #[clap(default_value = ".")]
+        paths: Vec<PathBuf>,
+
+        /// Only run the files whose path contains this text
+        #[clap(short, long)]
+        filter: Option<String>,
+    },
+
+    /// Replay the interactive sessions shown in Markdown files and compare their output
+    Doctest {
+        /// Markdown files with sessions in code blocks
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -68,19 +108,93 @@ fn main() {
         return;
     }
 
-    let env = StdEnvironment::new();
-    let mut interpreter = Interpreter::new(env);
-    interpreter.set_strategy(args.strategy);
-    interpreter.set_church_num_enabled(!args.strict);
-    if !args.strict {
-        interpreter.interpret_std().unwrap();
+    match &args.command {
+        Some(Command::Test { paths, filter }) => run_tests(&args, paths, filter.as_deref()),
+        Some(Command::Doctest { files }) => run_doctests(&args, files),
+        None => (),
     }
 
+    if let (Some(file), Some(expected)) = (&args.file, &args.check_output) {
+        check_output(&args, file, expected);
+    }
+
+    let mut interpreter = new_interpreter(&args, StdEnvironment::new());
     if let Some(file) = args.file {
         if let Err(e) = interpreter.interpret_file(file) {
             e.resolve();
         }
     } else {
+        if let Err(e) = interpreter.env().catch_interrupts() {
+            e.resolve();
+        }
         interactive::repl(&mut interpreter);
     }
 }
+
+/// An interpreter set up as requested on the command line
+fn new_interpreter<E: Environment>(args: &Args, env: E) -> Interpreter<E> {
+    let mut interpreter = Interpreter::new(env);
+    interpreter.set_strategy(args.strategy);
+    interpreter.set_church_num_enabled(!args.strict);
+    if !args.strict {
+        interpreter.interpret_std().unwrap();
+    }
+    for (key, value) in &args.settings {
+        if let Err(e) = interpreter.set(key, value) {
+            e.resolve();
+        }
+    }
+    interpreter
+}
+
+/// Run the test files in `paths` and exit with the result
+fn run_tests(args: &Args, paths: &[PathBuf], filter: Option<&str>) -> ! {
+    let files = match testing::discover(paths) {
+        Ok(files) => files,
+        Err(e) => { eprintln!("Error: {}", e); std::process::exit(1); }
+    };
+    let (selected, skipped): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter()
+        .partition(|file| testing::matches_filter(file, filter));
+
+    println!("running {} test{}", selected.len(), if selected.len() == 1 { "" } else { "s" });
+    let start = std::time::Instant::now();
+    let results = testing::run(&selected, || new_interpreter(args, CaptureEnvironment::new(StdEnvironment::new())));
+    let passed = testing::report(&results, skipped.len(), start.elapsed());
+    std::process::exit(if passed { 0 } else { 1 });
+}
+
+/// Replay the sessions in the Markdown `files` and exit with the result
+fn run_doctests(args: &Args, files: &[PathBuf]) -> ! {
+    let mut results = Vec::new();
+    let start = std::time::Instant::now();
+    for file in files {
+        match doctest::run(file, || new_interpreter(args, CaptureEnvironment::new(StdEnvironment::new()))) {
+            Ok(file_results) => results.extend(file_results),
+            Err(e) => { eprintln!("Error: {}: {}", file.display(), e); std::process::exit(1); }
+        }
+    }
+    println!("running {} test{}", results.len(), if results.len() == 1 { "" } else { "s" });
+    let passed = testing::report(&results, 0, start.elapsed());
+    std::process::exit(if passed { 0 } else { 1 });
+}
+
+/// Run `file`, compare its output with the contents of `expected` and exit with the result
+fn check_output(args: &Args, file: &Path, expected: &Path) -> ! {
+    let expected = match std::fs::read_to_string(expected) {
+        Ok(expected) => expected,
+        Err(e) => { eprintln!("Error: {}: {}", expected.display(), e); std::process::exit(1); }
+    };
+    let mut interpreter = new_interpreter(args, CaptureEnvironment::new(StdEnvironment::new()));
+    let output = testing::capture(&mut interpreter, file);
+    if let Err(e) = testing::check_output(&output, &expected) {
+        e.resolve();
+    }
+    std::process::exit(0);
+}
+
+fn parse_setting(setting: &str) -> Result<(String, String), String> {
+    match setting.split_once('=') {
+        Some((key, value)) => Ok((key.to_owned(), value.to_owned())),
+        None => Err(format!("expected KEY=VALUE, found '{}'", setting)),
+    }
+}
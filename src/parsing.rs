@@ -1,27 +1,68 @@
 extern crate alloc;
 
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::fmt::Display;
 use core::str::FromStr;
 
-use pest::{iterators::Pair, Parser};
+use pest::error::{ErrorVariant, InputLocation};
+use pest::iterators::{Pair, Pairs};
+use pest::Parser;
 use pest_derive::Parser;
 
-use crate::error::{LashError, LashResult};
-use crate::interpreter::InterpreterDirective;
+use crate::assertion::Assertion;
+use crate::data::{Constructor, DataType};
+use crate::error::{LashError, LashResult, Span};
+use crate::interpreter::{Import, InterpreterDirective};
 use crate::lambda::LambdaTree;
 use crate::r#macro::Macro;
+use crate::typing::TypeExpr;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct LambdaParser;
 
+/// Declared infix operators, by their symbol
+#[derive(Debug, Clone, Default)]
+pub struct OperatorTable {
+    operators: BTreeMap<String, Operator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operator {
+    pub associativity: Associativity,
+    pub precedence: u8,
+    /// Name of the term the operator is applied as
+    pub term: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+    None,
+}
+
+/// Turns the statements of a file into [`Statement`]s one at a time
+///
+/// The input is split into statements up front, but terms are only built when they are
+/// requested, so they can use the operators declared by the statements before them.
+/// Statements with syntax errors are skipped up to the next `;`, so the statements after
+/// them are still parsed.
+pub struct StatementParser<'a> {
+    input: &'a str,
+    pairs: Pairs<'a, Rule>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     Assignment(String, LambdaTree),
     Directive(InterpreterDirective),
     Lambda(LambdaTree),
+    RecAssignment(Vec<(String, LambdaTree)>),
 }
 
 
@@ -29,18 +70,149 @@ impl Display for Statement {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use Statement::*;
         match self {
-            Assignment(name, term) => write!(f, "{} := {}", name, term),
+            Assignment(name, term) => { write!(f, "{} := ", name)?; term.fmt(f) },
             Lambda(term) => term.fmt(f),
             Directive(directive) => directive.fmt(f),
+            RecAssignment(bindings) => {
+                for (i, (name, term)) in bindings.iter().enumerate() {
+                    if i == 0 {
+                        write!(f, "rec {} := ", name)?;
+                    } else {
+                        write!(f, "; and {} := ", name)?;
+                    }
+                    term.fmt(f)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+
+impl OperatorTable {
+    pub fn declare(&mut self, symbol: String, operator: Operator) {
+        self.operators.insert(symbol, operator);
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Operator> {
+        self.operators.get(symbol)
+    }
+}
+
+impl<'a> StatementParser<'a> {
+    pub fn new(input: &'a str) -> LashResult<Self> {
+        let parsed = LambdaParser::parse(Rule::statements, input)
+            .map_err(LashError::new_syntax_error)?
+            .next().unwrap();
+        Ok(StatementParser { input, pairs: parsed.into_inner() })
+    }
+
+    /// Parse the next statement, along with the position it starts at
+    pub fn next_statement(&mut self, operators: &OperatorTable) -> Option<LashResult<(Span, Statement)>> {
+        let pair = self.pairs.find(|ip| ip.as_rule() != Rule::EOI)?;
+        if pair.as_rule() == Rule::invalid {
+            return Some(Err(self.invalid_statement_error(&pair)));
         }
+        let span = span_of(&pair);
+        Some(parse_single_statement(pair, operators).map(|statement| (span, statement)))
+    }
+
+    /// Find out what is wrong with a statement that did not parse
+    ///
+    /// The statement is parsed again on its own, with the text before it blanked out,
+    /// so the error refers to the position in the whole input.
+    fn invalid_statement_error(&self, pair: &Pair<Rule>) -> LashError {
+        let start = pair.as_span().start();
+        let end = pair.as_span().end();
+        let padded: String = self.input[..start].chars()
+            .map(|c| if c == '\n' { c } else { ' ' })
+            .chain(self.input[start..end].chars())
+            .collect();
+        let padding = padded.len() - (end - start);
+
+        let error = match LambdaParser::parse(Rule::statement, &padded) {
+            Err(error) => error,
+            Ok(_) => pest::error::Error::new_from_pos(
+                ErrorVariant::CustomError { message: "expected ';'".to_string() },
+                pest::Position::new(&padded, padded.trim_end().len()).unwrap()),
+        };
+        let location = match error.location {
+            InputLocation::Pos(pos) => pos,
+            InputLocation::Span((pos, _)) => pos,
+        };
+        // report the error with the line of the original input
+        let offset = (location + start).saturating_sub(padding).min(self.input.len());
+        let position = pest::Position::new(self.input, offset).unwrap();
+        LashError::new_syntax_error(pest::error::Error::<Rule>::new_from_pos(error.variant, position))
     }
 }
 
+fn span_of(pair: &Pair<Rule>) -> Span {
+    // uses the line index of the parse, instead of counting the lines from the start of the input
+    let (line, column) = pair.line_col();
+    Span { line, column }
+}
+
+fn operator_error(pair: &Pair<Rule>, message: String) -> LashError {
+    let error = pest::error::Error::<Rule>::new_from_span(ErrorVariant::CustomError { message }, pair.as_span());
+    LashError::new_syntax_error(error)
+}
 
-fn parse_lambda(pair: Pair<Rule>) -> LashResult<LambdaTree> {
+/// Build the applications for a chain of operands and infix operators by precedence climbing
+fn parse_infix(pair: Pair<Rule>, operators: &OperatorTable) -> LashResult<LambdaTree> {
+    fn apply(operands: &mut Vec<LambdaTree>, operator: &Operator) {
+        let right = operands.pop().unwrap();
+        let left = operands.pop().unwrap();
+        let function = LambdaTree::new_variable(operator.term.clone());
+        operands.push(LambdaTree::new_application(LambdaTree::new_application(function, left), right));
+    }
+
+    let mut operands = Vec::new();
+    let mut pending: Vec<(&Operator, &str)> = Vec::new();
+    for sub_pair in pair.into_inner() {
+        if sub_pair.as_rule() != Rule::operator {
+            operands.push(parse_lambda(sub_pair, operators)?);
+            continue;
+        }
+
+        let symbol = sub_pair.as_str();
+        let operator = operators.get(symbol)
+            .ok_or_else(|| operator_error(&sub_pair, format!("unknown operator '{}'", symbol)))?;
+        while let Some((top, top_symbol)) = pending.last() {
+            if top.precedence == operator.precedence
+                    && (top.associativity != operator.associativity || operator.associativity == Associativity::None) {
+                return Err(operator_error(&sub_pair, format!("cannot mix '{}' and '{}' without parentheses", top_symbol, symbol)));
+            }
+            let binds_tighter = top.precedence > operator.precedence
+                || (top.precedence == operator.precedence && operator.associativity == Associativity::Left);
+            if !binds_tighter {
+                break;
+            }
+            apply(&mut operands, top);
+            pending.pop();
+        }
+        pending.push((operator, symbol));
+    }
+    while let Some((operator, _)) = pending.pop() {
+        apply(&mut operands, operator);
+    }
+    Ok(operands.pop().unwrap())
+}
+
+fn parse_lambda(pair: Pair<Rule>, operators: &OperatorTable) -> LashResult<LambdaTree> {
+    let span = span_of(&pair);
+    let term = parse_lambda_node(pair, operators)?;
+    // nested rules like `group` already know a more precise position
+    match term.span() {
+        Some(_) => Ok(term),
+        None => Ok(term.at(span)),
+    }
+}
+
+fn parse_lambda_node(pair: Pair<Rule>, operators: &OperatorTable) -> LashResult<LambdaTree> {
     use Rule::*;
     match pair.as_rule() {
-        lambda => parse_lambda(pair.into_inner().next().unwrap()),
+        lambda => parse_lambda(pair.into_inner().next().unwrap(), operators),
         abstraction => {
             let mut variables = Vec::new();
             let mut child = None;
@@ -48,7 +220,7 @@ fn parse_lambda(pair: Pair<Rule>) -> LashResult<LambdaTree> {
                 if sub_pair.as_rule() == variable {
                     variables.push(sub_pair.as_span().as_str().to_string());
                 } else {
-                    child = Some(parse_lambda(sub_pair)?);
+                    child = Some(parse_lambda(sub_pair, operators)?);
                     break
                 }
             }
@@ -61,7 +233,7 @@ fn parse_lambda(pair: Pair<Rule>) -> LashResult<LambdaTree> {
         },
         application => {
             let mut children: Vec<_> = pair.into_inner()
-                .map(|ip| parse_lambda(ip))
+                .map(|ip| parse_lambda(ip, operators))
                 .collect();
             if children.len() == 1 {
                 Ok(children.pop().unwrap()?)
@@ -74,56 +246,216 @@ fn parse_lambda(pair: Pair<Rule>) -> LashResult<LambdaTree> {
                 Ok(current)
             }
         },
-        group => parse_lambda(pair.into_inner().next().unwrap()),
-        variable => Ok(LambdaTree::new_variable(pair.as_span().as_str().to_string())),
+        let_expr => {
+            let mut bindings = Vec::new();
+            let mut body = None;
+            for sub_pair in pair.into_inner() {
+                match sub_pair.as_rule() {
+                    binding => {
+                        let mut inner = sub_pair.into_inner();
+                        let var = inner.next().unwrap().as_str().to_string();
+                        bindings.push((var, parse_lambda(inner.next().unwrap(), operators)?));
+                    },
+                    lambda => body = Some(parse_lambda(sub_pair, operators)?),
+                    _ => (),
+                }
+            }
+
+            let mut current = body.unwrap();
+            for (var, bound) in bindings.into_iter().rev() {
+                current = LambdaTree::new_let(var, bound, current);
+            }
+            Ok(current)
+        },
+        match_expr => {
+            let mut inner = pair.into_inner();
+            let scrutinee = parse_lambda(inner.nth(1).unwrap(), operators)?;
+            let mut arms = Vec::new();
+            for arm in inner.filter(|ip| ip.as_rule() == match_arm) {
+                let mut names: Vec<_> = arm.clone().into_inner()
+                    .filter(|ip| ip.as_rule() == variable)
+                    .map(|ip| ip.as_str().to_string())
+                    .collect();
+                let constructor = names.remove(0);
+                let mut handler = parse_lambda(arm.into_inner().last().unwrap(), operators)?;
+                for field in names.iter().rev() {
+                    handler = LambdaTree::new_abstraction(field.clone(), handler);
+                }
+                arms.push((constructor, names.len(), handler));
+            }
+            Ok(LambdaTree::new_match(scrutinee, arms))
+        },
+        infix | assert_operand => parse_infix(pair, operators),
+        group => parse_lambda(pair.into_inner().next().unwrap(), operators),
+        section => {
+            let symbol = pair.into_inner().next().unwrap();
+            match operators.get(symbol.as_str()) {
+                Some(declared) => Ok(LambdaTree::new_variable(declared.term.clone())),
+                None => Err(operator_error(&symbol, format!("unknown operator '{}'", symbol.as_str()))),
+            }
+        },
+        list => {
+            let mut items = Vec::new();
+            for item in pair.into_inner() {
+                items.push(parse_lambda(item, operators)?);
+            }
+            Ok(LambdaTree::new_list(items))
+        },
+        tuple => {
+            let mut inner = pair.into_inner();
+            let first = parse_lambda(inner.next().unwrap(), operators)?;
+            let second = parse_lambda(inner.next().unwrap(), operators)?;
+            Ok(LambdaTree::new_pair(first, second))
+        },
+        string => Ok(LambdaTree::new_string(pair.into_inner().next().unwrap().as_str())),
+        variable | qualified_name => Ok(LambdaTree::new_variable(pair.as_span().as_str().to_string())),
         church => Ok(LambdaTree::new_church_num(pair.as_span().as_str()[1..].parse::<u32>().unwrap())),
         r#macro => {
             let mut inner = pair.into_inner();
-            let name = inner.next().unwrap().as_span().as_str().to_string();
+            let name_pair = inner.next().unwrap();
+            let name = name_pair.as_str().to_string();
             let mut children = Vec::new();
-            for child in inner.map(|ip| parse_lambda(ip)) {
+            for child in inner.map(|ip| parse_lambda(ip, operators)) {
                 children.push(child?);
             }
             let m = Macro::from_str(&name)
-                .map_err(|_| LashError::new_unknown_macro_error(&name))?;
+                .map_err(|_| LashError::new_unknown_macro_error(&name).with_span(Some(span_of(&name_pair))))?;
             Ok(LambdaTree::new_macro(m, children))
         },
         _ => panic!("Unexpected token '{:?}'", pair.as_rule()),
     }
 }
 
-fn parse_single_statement(pair: Pair<Rule>) -> LashResult<Statement> {
+fn parse_type(pair: Pair<Rule>) -> TypeExpr {
+    let mut inner = pair.into_inner();
+    let first = inner.next().unwrap();
+    let argument = match first.as_rule() {
+        Rule::type_var => TypeExpr::Var(first.as_str().to_string()),
+        _ => parse_type(first),
+    };
+    match inner.next() {
+        Some(result) => TypeExpr::Arrow(Box::new(argument), Box::new(parse_type(result))),
+        None => argument,
+    }
+}
+
+fn parse_single_statement(pair: Pair<Rule>, operators: &OperatorTable) -> LashResult<Statement> {
     use Rule::*;
     match pair.as_rule() {
-        statement => parse_single_statement(pair.into_inner().next().unwrap()),
+        statement => parse_single_statement(pair.into_inner().next().unwrap(), operators),
         assignment => {
             let mut inner = pair.into_inner();
             let name = inner.next().unwrap().as_str();
-            let term = parse_lambda(inner.next().unwrap())?;
+            let term = parse_lambda(inner.next().unwrap(), operators)?;
             Ok(Statement::Assignment(name.to_string(), term))
         },
-        lambda => Ok(Statement::Lambda(parse_lambda(pair.into_inner().next().unwrap())?)),
+        rec_group => {
+            let mut bindings = Vec::new();
+            for sub_pair in pair.into_inner().filter(|ip| ip.as_rule() == binding) {
+                let mut inner = sub_pair.into_inner();
+                let name = inner.next().unwrap().as_str().to_string();
+                bindings.push((name, parse_lambda(inner.next().unwrap(), operators)?));
+            }
+            Ok(Statement::RecAssignment(bindings))
+        },
+        lambda => Ok(Statement::Lambda(parse_lambda(pair.into_inner().next().unwrap(), operators)?)),
         directive => {
             let mut inner = pair.into_inner();
             let dir_pair = inner.next().unwrap();
             match dir_pair.as_rule() {
                 directive_usestd => Ok(Statement::Directive(InterpreterDirective::UseStd)),
+                directive_assert | directive_assert_alpha | directive_assert_type | directive_assert_diverges => {
+                    let kind = dir_pair.as_rule();
+                    let mut inner = dir_pair.into_inner().filter(|ip| ip.as_rule() != kw_assert);
+                    let term = parse_lambda(inner.next().unwrap(), operators)?;
+                    let assertion = match kind {
+                        directive_assert => Assertion::Equal(term, parse_lambda(inner.next().unwrap(), operators)?),
+                        directive_assert_alpha => Assertion::AlphaEqual(term, parse_lambda(inner.next().unwrap(), operators)?),
+                        directive_assert_type => Assertion::Type(term, parse_type(inner.next().unwrap())),
+                        _ => Assertion::Diverges(term),
+                    };
+                    Ok(Statement::Directive(InterpreterDirective::Assert(assertion)))
+                },
+                directive_begin => Ok(Statement::Directive(InterpreterDirective::Begin)),
+                directive_end => Ok(Statement::Directive(InterpreterDirective::End)),
+                directive_env => {
+                    let pattern = dir_pair.into_inner().next().map(|ip| ip.as_str().to_string());
+                    Ok(Statement::Directive(InterpreterDirective::Env(pattern)))
+                },
+                directive_show => {
+                    let name = dir_pair.into_inner().next().unwrap().as_str().to_string();
+                    Ok(Statement::Directive(InterpreterDirective::Show(name)))
+                },
+                directive_undef => {
+                    let name = dir_pair.into_inner().next().unwrap().as_str().to_string();
+                    Ok(Statement::Directive(InterpreterDirective::Undef(name)))
+                },
+                directive_deps => {
+                    let name = dir_pair.into_inner().next().map(|ip| ip.as_str().to_string());
+                    Ok(Statement::Directive(InterpreterDirective::Deps(name)))
+                },
+                directive_unused => Ok(Statement::Directive(InterpreterDirective::Unused)),
                 directive_set => {
                     let mut inner = dir_pair.into_inner();
                     let k = inner.next().unwrap().as_span().as_str().to_string();
-                    let v = inner.next().unwrap().as_span().as_str().to_string();
+                    let value_pair = inner.next().unwrap();
+                    // quoted values are passed on without the quotes
+                    let v = match value_pair.clone().into_inner().next() {
+                        Some(quoted) => quoted.as_str().to_string(),
+                        None => value_pair.as_str().to_string(),
+                    };
                     Ok(Statement::Directive(InterpreterDirective::Set(k, v)))
                 },
+                directive_get => {
+                    let k = dir_pair.into_inner().next().unwrap().as_str().to_string();
+                    Ok(Statement::Directive(InterpreterDirective::Get(k)))
+                },
+                directive_settings => Ok(Statement::Directive(InterpreterDirective::Settings)),
                 directive_echo => {
                     let mut inner = dir_pair.into_inner();
                     let msg = inner.next().unwrap().as_span().as_str().to_string();
                     Ok(Statement::Directive(InterpreterDirective::Echo(msg)))
                 }
+                directive_data => {
+                    let mut inner = dir_pair.into_inner();
+                    let name = inner.next().unwrap().as_str().to_string();
+                    let constructors = inner.map(|constructor| {
+                        let mut names = constructor.into_inner().map(|ip| ip.as_str().to_string());
+                        let name = names.next().unwrap();
+                        Constructor::new(name, names.collect())
+                    }).collect();
+                    Ok(Statement::Directive(InterpreterDirective::Data(DataType::new(name, constructors))))
+                },
+                directive_infix => {
+                    let mut inner = dir_pair.into_inner();
+                    let associativity = match inner.next().unwrap().as_str() {
+                        "infixl" => Associativity::Left,
+                        "infixr" => Associativity::Right,
+                        _ => Associativity::None,
+                    };
+                    let level = inner.next().unwrap().as_str().parse().unwrap();
+                    let symbol = inner.next().unwrap().as_str().to_string();
+                    let term = inner.next().unwrap().as_str().to_string();
+                    let declared = Operator { associativity, precedence: level, term };
+                    Ok(Statement::Directive(InterpreterDirective::Infix(symbol, declared)))
+                },
                 directive_include => {
                     let mut inner = dir_pair.into_inner();
                     let path = inner.next().unwrap().as_span().as_str().to_string();
                     Ok(Statement::Directive(InterpreterDirective::Include(path)))
                 }
+                directive_import => {
+                    let mut import = Import { file: String::new(), alias: None, names: None };
+                    for ip in dir_pair.into_inner() {
+                        match ip.as_rule() {
+                            file_path => import.file = ip.into_inner().next().unwrap().as_str().to_string(),
+                            variable => import.alias = Some(ip.as_str().to_string()),
+                            import_list => import.names = Some(ip.into_inner().map(|n| n.as_str().to_string()).collect()),
+                            _ => (),
+                        }
+                    }
+                    Ok(Statement::Directive(InterpreterDirective::Import(import)))
+                }
                 _ => unreachable!(),
             }
         },
@@ -131,26 +463,19 @@ fn parse_single_statement(pair: Pair<Rule>) -> LashResult<Statement> {
     }
 }
 
-fn parse_multi_statement(pair: Pair<Rule>) -> LashResult<Vec<Statement>> {
-    let mut statements = Vec::new();
-    for result in pair.into_inner()
-            .filter(|ip| ip.as_rule() != Rule::EOI)
-            .map(|ip| parse_single_statement(ip)) {
-        statements.push(result?);
-    }
-    Ok(statements)
-}
-
-pub fn parse_statement(input: &str) -> LashResult<Statement> {
+pub fn parse_statement(input: &str, operators: &OperatorTable) -> LashResult<Statement> {
     let parsed = LambdaParser::parse(Rule::statement, input)
-        .map_err(|e| LashError::new_syntax_error(e))?
+        .map_err(LashError::new_syntax_error)?
         .next().unwrap();
-    parse_single_statement(parsed)
+    parse_single_statement(parsed, operators)
 }
 
-pub fn parse_statements(input: &str) -> LashResult<Vec<Statement>> {
-    let parsed = LambdaParser::parse(Rule::statements, input)
-        .map_err(|e| LashError::new_syntax_error(e))?
-        .next().unwrap();
-    parse_multi_statement(parsed)
+#[allow(dead_code)]
+pub fn parse_statements(input: &str, operators: &OperatorTable) -> LashResult<Vec<Statement>> {
+    let mut parser = StatementParser::new(input)?;
+    let mut statements = Vec::new();
+    while let Some(statement) = parser.next_statement(operators) {
+        statements.push(statement?.1);
+    }
+    Ok(statements)
 }
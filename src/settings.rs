@@ -0,0 +1,183 @@
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::time::Duration;
+
+use crate::error::{LashError, LashResult};
+
+
+/// The kind of values a setting accepts
+#[derive(Debug, Clone, Copy)]
+pub enum ValueKind {
+    Bool,
+    Integer,
+    Duration,
+    /// One of the given names
+    Enum(&'static [&'static str]),
+    String,
+}
+
+/// Value of a setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// The setting is turned off, only valid for optional settings
+    Off,
+    Bool(bool),
+    Integer(usize),
+    Duration(Duration),
+    Enum(String),
+    String(String),
+}
+
+/// A key that can be changed with `@set`
+#[derive(Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub kind: ValueKind,
+    /// Whether the setting can be turned `off`
+    pub optional: bool,
+    pub description: &'static str,
+}
+
+/// All settings, sorted by their keys
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "binding",
+        kind: ValueKind::Enum(&["early", "late"]),
+        optional: false,
+        description: "whether definitions use the current or the latest definitions of names",
+    },
+    Setting {
+        key: "encoding",
+        kind: ValueKind::Enum(&["scott", "church"]),
+        optional: false,
+        description: "encoding of the terms generated by @data",
+    },
+    Setting {
+        key: "fuel",
+        kind: ValueKind::Integer,
+        optional: false,
+        description: "reductions after which assertions consider a term diverging",
+    },
+    Setting {
+        key: "maxsize",
+        kind: ValueKind::Integer,
+        optional: true,
+        description: "abort normalizations once the term grows beyond this many nodes",
+    },
+    Setting {
+        key: "numerals",
+        kind: ValueKind::Bool,
+        optional: false,
+        description: "enable church numerals",
+    },
+    Setting {
+        key: "prompt",
+        kind: ValueKind::String,
+        optional: false,
+        description: "prompt of the interactive mode",
+    },
+    Setting {
+        key: "readback",
+        kind: ValueKind::Enum(&["false", "true", "strings"]),
+        optional: false,
+        description: "print lists and pairs as literals, with strings also strings",
+    },
+    Setting {
+        key: "strategy",
+        kind: ValueKind::Enum(&["applicative", "normal", "callbyname"]),
+        optional: false,
+        description: "reduction strategy",
+    },
+    Setting {
+        key: "timeout",
+        kind: ValueKind::Duration,
+        optional: true,
+        description: "abort normalizations that take longer than this",
+    },
+    Setting {
+        key: "warnings",
+        kind: ValueKind::Enum(&["error", "warn", "off"]),
+        optional: false,
+        description: "turn warnings into errors, print them or ignore them",
+    },
+];
+
+
+impl Setting {
+    /// Look up the setting for `key`
+    pub fn find(key: &str) -> LashResult<&'static Setting> {
+        SETTINGS.iter().find(|s| s.key == key).ok_or_else(|| {
+            let keys: Vec<&str> = SETTINGS.iter().map(|s| s.key).collect();
+            LashError::new_set_key_error(key, &keys)
+        })
+    }
+
+    pub fn parse(&self, value: &str) -> LashResult<Value> {
+        let parsed = match self.kind {
+            _ if self.optional && value == "off" => Some(Value::Off),
+            ValueKind::Bool => value.parse().ok().map(Value::Bool),
+            ValueKind::Integer => value.parse().ok().map(Value::Integer),
+            ValueKind::Duration => parse_duration(value).map(Value::Duration),
+            ValueKind::Enum(names) => names.contains(&value).then(|| Value::Enum(value.to_owned())),
+            ValueKind::String => Some(Value::String(value.to_owned())),
+        };
+        parsed.ok_or_else(|| LashError::new_set_value_error(self.key, value, &self.expected()))
+    }
+
+    /// Description of the values the setting accepts
+    pub fn expected(&self) -> String {
+        let expected = match self.kind {
+            ValueKind::Bool => "true|false".to_owned(),
+            ValueKind::Integer => "a number".to_owned(),
+            ValueKind::Duration => "a duration like 500ms, 5s, 2m or 1h".to_owned(),
+            ValueKind::Enum(names) => names.join("|"),
+            ValueKind::String => "a string".to_owned(),
+        };
+        if self.optional {
+            format!("{} or off", expected)
+        } else {
+            expected
+        }
+    }
+}
+
+/// Parse durations like `500ms`, `5s`, `2m` or `1h`
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => amount.checked_mul(60).map(Duration::from_secs),
+        "h" => amount.checked_mul(60 * 60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Values are printed in the form `@set` accepts
+impl Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Value::Off => write!(f, "off"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Duration(duration) => {
+                let millis = duration.as_millis();
+                match millis {
+                    _ if millis % 3_600_000 == 0 && millis > 0 => write!(f, "{}h", millis / 3_600_000),
+                    _ if millis % 60_000 == 0 && millis > 0 => write!(f, "{}m", millis / 60_000),
+                    _ if millis % 1000 == 0 => write!(f, "{}s", millis / 1000),
+                    _ => write!(f, "{}ms", millis),
+                }
+            },
+            Value::Enum(name) => write!(f, "{}", name),
+            Value::String(s) => write!(f, "\"{}\"", s),
+        }
+    }
+}
@@ -1,6 +1,11 @@
+extern crate alloc;
+
+use crate::lambda::{NamedTerm, Shared};
+use crate::parsing::{self, Statement};
+
+
 pub const STD: &str = "K	    := \\x . \\y . x;
 ADD	    := \\m . \\n . \\f . \\x . m f (n f x);
-NOT	    := \\p . p FALSE TRUE;
 OMEGA	:= \\x . x x;
 PAIR	:= \\x . \\y . \\z . z x y;
 CONS	:= \\h . \\t . \\c . \\n . c h (t c n);
@@ -10,6 +15,7 @@ FALSE	:= \\x . \\y . y;
 ID	    := \\x . x;
 S	    := \\x . \\y . \\z . x z (y z);
 TRUE	:= \\x . \\y . x;
+NOT	    := \\p . p FALSE TRUE;
 W	    := \\x . \\y . x y y;
 SECOND	:= \\p . p FALSE;
 ISNIL	:= \\l . l (\\h . \\t . FALSE) TRUE;
@@ -26,3 +32,17 @@ TAIL	:= \\l . \\c . \\n . l (\\h . \\t . \\g . g h (t c)) (\\t . n) (\\h . \\t .
 EXP	    := \\m . \\n . n m;
 SUB	    := \\m . \\n . n PRED m;
 ";
+
+/// The definition of `name` in the standard library, which list, pair and string literals are built from
+///
+/// Literals always use these definitions of `CONS`, `NIL` and `PAIR`, so they work without `@usestd`
+/// and do not change when the names are redefined.
+pub fn literal_encoding(name: &str) -> Shared<NamedTerm> {
+    let definition = STD.lines()
+        .find(|line| line.split_whitespace().next() == Some(name))
+        .expect("literals are built from terms of the standard library");
+    match parsing::parse_statement(definition.trim_end_matches(';'), &Default::default()) {
+        Ok(Statement::Assignment(name, term)) => Shared::new(NamedTerm::new(name, term)),
+        _ => unreachable!("the standard library only contains assignments"),
+    }
+}
@@ -2,35 +2,121 @@ extern crate alloc;
 
 use alloc::borrow::ToOwned;
 use alloc::format;
-use alloc::string::String;
-use core::fmt::Write;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Write};
 use core::str::FromStr;
+use core::time::Duration;
 
 #[cfg(feature = "std")]
 use colored::Colorize;
 
+use crate::environment::Environment;
+#[cfg(all(feature = "sync", feature = "std"))]
+use crate::environment::{Interrupts, StdEnvironment};
+use crate::error::{LashError, LashResult};
+use crate::hashcons::TermTable;
 use crate::lambda::*;
 
 
-#[derive(Debug, Copy, Clone)]
+/// Reductions between two times the current term is interned
+///
+/// Most nodes built by a reduction are replaced by the next one, interning only every few
+/// reductions shares the nodes that last without paying for the others.
+const INTERN_INTERVAL: usize = 16;
+
+
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(clap::ValueEnum))]
 #[cfg_attr(feature = "std", clap(rename_all = "lower"))]
 pub enum Strategy {
     Applicative,
+    #[default]
     Normal,
     CallByName,
 }
 
+/// Bounds that abort a normalization when exceeded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub timeout: Option<Duration>,
+    pub max_size: Option<usize>,
+    /// Number of reductions after which a term counts as diverging
+    pub max_reductions: Option<usize>,
+}
+
+/// A subterm on the path to the redex that is currently searched for
+struct Frame {
+    term: LambdaTree,
+    children: Vec<LambdaTree>,
+    next_child: usize,
+    contract_after_children: bool,
+}
+
 impl Strategy {
-    pub fn normalize(&self, term: LambdaTree, verbose: bool, out: &mut impl Write) -> (LambdaTree, usize) {
-        let mut current = term;
+    pub const ALL: [Strategy; 3] = [Strategy::Applicative, Strategy::Normal, Strategy::CallByName];
+
+    /// Normalize the term with every strategy
+    ///
+    /// With the `sync` feature the strategies run in parallel, each on its own thread.
+    pub fn compare<E: Environment>(term: LambdaTree, limits: &Limits, env: &mut E) -> Vec<(Strategy, LashResult<(LambdaTree, usize)>)> {
+        // a request to cancel stops all strategies, not just the one that is running
+        env.begin_interruptible();
+        #[cfg(all(feature = "sync", feature = "std"))]
+        let results = {
+            let jobs = Self::ALL.iter().map(|s| (*s, term.clone())).collect();
+            Self::ALL.into_iter().zip(normalize_concurrently(jobs, limits, &env.interrupts())).collect()
+        };
+        #[cfg(not(all(feature = "sync", feature = "std")))]
+        let results = Self::ALL.into_iter()
+            .map(|s| (s, s.normalize(term.clone(), false, limits, env)))
+            .collect();
+        env.end_interruptible();
+        results
+    }
+
+    /// Reduce the term until it is in normal form or one of the limits is exceeded
+    ///
+    /// The normalization can be cancelled through [`Environment::interrupted`].
+    pub fn normalize<E: Environment>(&self, term: LambdaTree, verbose: bool, limits: &Limits, env: &mut E) -> LashResult<(LambdaTree, usize)> {
+        env.begin_interruptible();
+        let result = self.normalize_within(term, verbose, limits, env);
+        env.end_interruptible();
+        result
+    }
+
+    fn normalize_within<E: Environment>(&self, term: LambdaTree, verbose: bool, limits: &Limits, env: &mut E) -> LashResult<(LambdaTree, usize)> {
+        let time_start = env.now();
+        // reducts share their unchanged parts, interning makes the new parts that last shared as well
+        let mut terms = TermTable::new();
+        let mut current = terms.intern(&term);
         let mut nreductions = 0;
         loop {
-            if let Some(next) = self.reduce(current.clone(), verbose, out) {
-                current = next;
+            if env.interrupted() {
+                return Err(LashError::new_interrupt_error(nreductions));
+            }
+            if limits.max_reductions.is_some_and(|max| nreductions >= max) {
+                return Err(LashError::new_fuel_error(nreductions));
+            }
+            if let Some(timeout) = limits.timeout {
+                if env.elapsed(time_start) > timeout {
+                    return Err(LashError::new_timeout_error(timeout, nreductions));
+                }
+            }
+
+            if let Some(next) = self.reduce(current.clone(), verbose, env.stdout()) {
                 nreductions += 1;
+                current = if nreductions % INTERN_INTERVAL == 0 { terms.intern(&next) } else { next };
+                if let Some(max_size) = limits.max_size {
+                    let size = current.size();
+                    if size > max_size {
+                        return Err(LashError::new_size_error(max_size, size, nreductions));
+                    }
+                }
             } else {
-                return (current, nreductions);
+                return Ok((terms.intern(&current), nreductions));
             }
         }
     }
@@ -38,9 +124,9 @@ impl Strategy {
     pub fn reduce(&self, term: LambdaTree, verbose: bool, out: &mut impl Write) -> Option<LambdaTree> {
         use Strategy::*;
         let result = match self {
-            Applicative => Self::reduce_applicative(term, verbose),
-            Normal => Self::reduce_normal(term, verbose),
-            CallByName => Self::reduce_cbn(term, verbose),
+            // call-by-name does not reduce under an outermost abstraction, but otherwise behaves like normal order
+            CallByName if term.is_abstraction() => None,
+            _ => self.find_and_contract(term, verbose),
         };
         if let Some((lambda, string)) = result {
             if verbose {
@@ -52,150 +138,91 @@ impl Strategy {
         }
     }
 
-    fn reduce_cbn(term: LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
-        use LambdaNode::*;
-        match term.node() {
-            Abstraction(..) => None,
-            Application(left_term, right_term) => {
-                if let Abstraction(var_name, inner_term) = left_term.node() {
-                    let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                    return Some((inner_term.substitute(var_name, right_term.clone()), string));
+    /// Search the next redex in the order given by the strategy, contract it and rebuild the term around it
+    ///
+    /// The search keeps the path from the root to the current subterm on an explicit stack,
+    /// so arbitrarily deep terms can be reduced.
+    fn find_and_contract(&self, term: LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
+        let mut path: Vec<Frame> = Vec::new();
+        let mut next = Some(term);
+        let contracted = loop {
+            if let Some(term) = next.take() {
+                if let Some(contracted) = self.contract_before_children(&term, verbose) {
+                    break contracted;
                 }
+                path.push(self.frame(term));
+            }
 
-                if let Named(named) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = named.term().node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
+            let frame = path.last_mut()?;
+            if frame.next_child < frame.children.len() {
+                next = Some(frame.children[frame.next_child].clone());
+                frame.next_child += 1;
+                continue;
+            }
 
-                if let ChurchNum(d) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = LambdaTree::unwrap_church_num(*d).node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
+            // all children are in normal form
+            let frame = path.pop().unwrap();
+            if frame.contract_after_children {
+                break Self::contract(&frame.term, verbose).unwrap();
+            }
+        };
 
-                if left_term.is_abstraction() {
-                    None
-                } else if let Some((left_reduced, left_string)) = Self::reduce_normal(left_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), left_string, right_term.clone(), None, verbose);
-                    Some((LambdaTree::new_application(left_reduced, right_term.clone()), string))
-                } else if let Some((right_reduced, right_string)) = Self::reduce_normal(right_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), None, right_term.clone(), right_string, verbose);
-                    Some((LambdaTree::new_application(left_term.clone(), right_reduced), string))
-                } else {
-                    None
-                }
-            },
-            Variable(_) => None,
-            Macro(..) => None,
-            Named(named) => Self::reduce_normal(named.term(), verbose),
-            ChurchNum(d) => Self::reduce_normal(LambdaTree::unwrap_church_num(*d), verbose),
+        let (mut term, mut string) = contracted;
+        while let Some(frame) = path.pop() {
+            (term, string) = frame.rebuild(term, string, verbose);
         }
+        Some((term, string))
     }
 
-    fn reduce_normal(term: LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
+    /// Contract the redex at the root of `term` if the strategy prefers it over the redexes inside
+    fn contract_before_children(&self, term: &LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
         use LambdaNode::*;
-        match term.node() {
-            Abstraction(var_name, term) => {
-                let inner_reduced = Self::reduce_normal(term.clone(), verbose);
-                if let Some((term, inner_string)) = inner_reduced {
-                    let string = inner_string.map(|s| format!("{}{} . {}", '\\', var_name, s));
-                    Some((LambdaTree::new_abstraction(var_name.to_owned(), term), string))
-                } else {
-                    None
-                }
-            },
-            Application(left_term, right_term) => {
-                if let Abstraction(var_name, inner_term) = left_term.node() {
-                    let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                    return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                }
-
-                if let Named(named) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = named.term().node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
+        match (self, term.node()) {
+            (Strategy::Applicative, Application(left_term, _)) if left_term.is_abstraction() => None,
+            (Strategy::Applicative, Let(..)) => None,
+            (_, Application(..) | Let(..)) => Self::contract(term, verbose),
+            _ => None,
+        }
+    }
 
-                if let ChurchNum(d) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = LambdaTree::unwrap_church_num(*d).node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
+    /// Contract `term` if it is a redex
+    fn contract(term: &LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
+        use LambdaNode::*;
+        if let Let(var_name, value, body) = term.node() {
+            let string = Self::reduction_format_let(term, verbose);
+            return Some((body.substitute(var_name, value.clone()), string));
+        }
 
-                if left_term.is_abstraction() {
-                    None
-                } else if let Some((left_reduced, left_string)) = Self::reduce_normal(left_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), left_string, right_term.clone(), None, verbose);
-                    Some((LambdaTree::new_application(left_reduced, right_term.clone()), string))
-                } else if let Some((right_reduced, right_string)) = Self::reduce_normal(right_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), None, right_term.clone(), right_string, verbose);
-                    Some((LambdaTree::new_application(left_term.clone(), right_reduced), string))
-                } else {
-                    None
-                }
-            },
-            Variable(_) => None,
-            Macro(..) => None,
-            Named(named) => Self::reduce_normal(named.term(), verbose),
-            ChurchNum(d) => Self::reduce_normal(LambdaTree::unwrap_church_num(*d), verbose),
+        let Application(left_term, right_term) = term.node() else { return None };
+        let function = match left_term.node() {
+            Abstraction(..) => left_term.clone(),
+            Named(named) => named.unfold(),
+            ChurchNum(d) => LambdaTree::unwrap_church_num(*d),
+            _ => return None,
+        };
+        if let Abstraction(var_name, inner_term) = function.node() {
+            let string = Self::reduction_format_redex(left_term, right_term, verbose);
+            Some((inner_term.substitute(var_name, right_term.clone()), string))
+        } else {
+            None
         }
     }
 
-    fn reduce_applicative(term: LambdaTree, verbose: bool) -> Option<(LambdaTree, Option<String>)> {
+    fn frame(&self, term: LambdaTree) -> Frame {
         use LambdaNode::*;
-        match term.node() {
-            Abstraction(var_name, term) => {
-                let inner_reduced = Self::reduce_applicative(term.clone(), verbose);
-                if let Some((term, inner_string)) = inner_reduced {
-                    let string = inner_string.map(|s| format!("{}{} . {}", '\\', var_name, s));
-                    Some((LambdaTree::new_abstraction(var_name.to_owned(), term), string))
-                } else {
-                    None
-                }
-            },
+        let (children, contract_after_children) = match term.node() {
+            Abstraction(_, inner_term) => (vec![inner_term.clone()], false),
             Application(left_term, right_term) => {
-                if let Abstraction(var_name, inner_term) = left_term.node() {
-                    let inner_reduced = Self::reduce_applicative(inner_term.clone(), verbose);
-                    if inner_reduced.is_none() && Self::reduce_applicative(right_term.clone(), verbose).is_none() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
-
-                if let Named(named) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = named.term().node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
-
-                if let ChurchNum(d) = left_term.node() {
-                    if let Abstraction(var_name, inner_term) = LambdaTree::unwrap_church_num(*d).node() {
-                        let string = Self::reduction_format_redex(left_term, right_term, verbose);
-                        return Some((inner_term.substitute(var_name, right_term.clone()), string));
-                    }
-                }
-
-                if let Some((left_reduced, left_string)) = Self::reduce_applicative(left_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), left_string, right_term.clone(), None, verbose);
-                    Some((LambdaTree::new_application(left_reduced, right_term.clone()), string))
-                } else if let Some((right_reduced, right_string)) = Self::reduce_applicative(right_term.clone(), verbose) {
-                    let string = Self::reduction_format_application(left_term.clone(), None, right_term.clone(), right_string, verbose);
-                    Some((LambdaTree::new_application(left_term.clone(), right_reduced), string))
-                } else {
-                    None
-                }
+                let contract_after = matches!(self, Strategy::Applicative) && left_term.is_abstraction();
+                (vec![left_term.clone(), right_term.clone()], contract_after)
             },
-            Variable(_) => None,
-            Macro(..) => None,
-            Named(named) => Self::reduce_applicative(named.term(), verbose),
-            ChurchNum(d) => Self::reduce_applicative(LambdaTree::unwrap_church_num(*d), verbose),
-        }
+            Let(_, value, body) => (vec![value.clone(), body.clone()], matches!(self, Strategy::Applicative)),
+            Variable(_) => (Vec::new(), false),
+            Macro(..) | Match(..) => (Vec::new(), false),
+            Named(named) => (vec![named.unfold()], false),
+            ChurchNum(d) => (vec![LambdaTree::unwrap_church_num(*d)], false),
+        };
+        Frame { term, children, next_child: 0, contract_after_children }
     }
 
     fn reduction_format_application(left_term: LambdaTree, left_string: Option<String>,
@@ -223,6 +250,18 @@ impl Strategy {
         }
     }
 
+    fn reduction_format_let(term: &LambdaTree, verbose: bool) -> Option<String> {
+        if verbose {
+            #[cfg(feature = "std")]
+            let result = Some(term.to_string().blue().to_string());
+            #[cfg(not(feature = "std"))]
+            let result = Some(term.to_string());
+            result
+        } else {
+            None
+        }
+    }
+
     fn reduction_format_redex(left_term: &LambdaTree, right_term: &LambdaTree, verbose: bool) -> Option<String> {
         if verbose {
             #[cfg(feature = "std")]
@@ -240,9 +279,63 @@ impl Strategy {
     }
 }
 
-impl Default for Strategy {
-    fn default() -> Self {
-        Self::Normal
+/// Normalize independent terms in parallel, each with its own strategy and thread
+///
+/// All normalizations are cancelled by the given requests.
+#[cfg(all(feature = "sync", feature = "std"))]
+pub fn normalize_concurrently(jobs: Vec<(Strategy, LambdaTree)>, limits: &Limits, interrupts: &Interrupts)
+        -> Vec<LashResult<(LambdaTree, usize)>> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = jobs.into_iter()
+            .map(|(strategy, term)| {
+                let mut env = StdEnvironment::with_interrupts(interrupts.clone());
+                scope.spawn(move || strategy.normalize(term, false, limits, &mut env))
+            })
+            .collect();
+        handles.into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+impl Frame {
+    /// Put a reduced child back into the term of this frame
+    fn rebuild(self, reduced: LambdaTree, string: Option<String>, verbose: bool) -> (LambdaTree, Option<String>) {
+        use LambdaNode::*;
+        match self.term.node() {
+            Abstraction(var_name, _) => {
+                let string = string.map(|s| format!("{}{} . {}", '\\', var_name, s));
+                (LambdaTree::new_abstraction(var_name.to_owned(), reduced), string)
+            },
+            Application(left_term, right_term) => if self.next_child == 1 {
+                let string = Strategy::reduction_format_application(left_term.clone(), string, right_term.clone(), None, verbose);
+                (LambdaTree::new_application(reduced, right_term.clone()), string)
+            } else {
+                let string = Strategy::reduction_format_application(left_term.clone(), None, right_term.clone(), string, verbose);
+                (LambdaTree::new_application(left_term.clone(), reduced), string)
+            },
+            Let(var_name, value, body) => if self.next_child == 1 {
+                let string = string.map(|s| format!("let {} := {} in {}", var_name, s, body));
+                (LambdaTree::new_let(var_name.to_owned(), reduced, body.clone()), string)
+            } else {
+                let string = string.map(|s| format!("let {} := {} in {}", var_name, value, s));
+                (LambdaTree::new_let(var_name.to_owned(), value.clone(), reduced), string)
+            },
+            // named terms and numerals are replaced by their reduced definition
+            Named(_) | ChurchNum(_) => (reduced, string),
+            Variable(_) | Macro(..) | Match(..) => unreachable!(),
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::Applicative => "applicative",
+            Self::Normal => "normal",
+            Self::CallByName => "callbyname",
+        };
+        write!(f, "{}", name)
     }
 }
 
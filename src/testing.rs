@@ -0,0 +1,204 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::environment::{CaptureEnvironment, Environment};
+use crate::error::{LashError, LashResult};
+use crate::interpreter::Interpreter;
+
+/// Unchanged lines shown around the differences in the output
+const DIFF_CONTEXT: usize = 2;
+
+/// Start of the lines `!time` prints, the durations differ from run to run
+const TIME_PREFIX: &str = "Time elapsed: ";
+
+
+/// Outcome of a single test
+pub struct TestResult {
+    /// The file that was run, or the part of it
+    pub name: String,
+    pub duration: Duration,
+    pub error: Option<LashError>,
+}
+
+/// Find the `.lsh` files in `paths`, directories are searched recursively
+///
+/// Files that are given explicitly are used whatever their extension is.
+pub fn discover(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+        for entry in std::fs::read_dir(&path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == "lsh") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Run each file in an interpreter of its own
+///
+/// A file passes if it runs without errors, or, if it contains expected output, if it prints
+/// exactly that output.
+pub fn run<E: Environment>(files: &[PathBuf], mut new_interpreter: impl FnMut() -> Interpreter<CaptureEnvironment<E>>)
+        -> Vec<TestResult> {
+    files.iter()
+        .map(|file| {
+            let mut interpreter = new_interpreter();
+            let contents = std::fs::read_to_string(file).unwrap_or_default();
+            let start = Instant::now();
+            let error = match embedded_output(&contents) {
+                Some(expected) => check_output(&capture(&mut interpreter, file), &expected).err(),
+                None => interpreter.interpret_file(file.clone()).err(),
+            };
+            TestResult { name: file.display().to_string(), duration: start.elapsed(), error }
+        })
+        .collect()
+}
+
+/// Run `file` and return everything it printed, followed by the error that stopped it
+///
+/// The path of the file is replaced by its name, so the output does not depend on where it is run from.
+pub fn capture<E: Environment>(interpreter: &mut Interpreter<CaptureEnvironment<E>>, file: &Path) -> String {
+    let result = interpreter.interpret_file(file.to_path_buf());
+    let mut output = interpreter.env().take_output();
+    if let Err(e) = result {
+        output.push_str(&format!("{}\n", e));
+    }
+    let name = file.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    output.replace(&*file.to_string_lossy(), &name)
+}
+
+/// Expected output written into a script as `{- output: ... -}` blocks, `None` if it has none
+///
+/// The blocks start and end on lines of their own, their contents are joined.
+pub fn embedded_output(contents: &str) -> Option<String> {
+    let mut expected: Option<String> = None;
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() != "{- output:" {
+            continue;
+        }
+        let block = expected.get_or_insert_with(String::new);
+        for line in lines.by_ref().take_while(|l| l.trim() != "-}") {
+            block.push_str(line);
+            block.push('\n');
+        }
+    }
+    expected
+}
+
+/// Compare the output of a script with the expected one
+///
+/// The durations printed by `!time` are not compared.
+pub fn check_output(output: &str, expected: &str) -> LashResult<()> {
+    match diff(&mask_timings(expected), &mask_timings(output)) {
+        Some(diff) => Err(LashError::new_output_mismatch_error(diff)),
+        None => Ok(()),
+    }
+}
+
+/// Line by line differences between two texts, `None` if they agree
+///
+/// Lines only found in `expected` are marked with `-`, those only found in `actual` with `+`.
+/// Trailing whitespace is ignored.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    let (expected, actual) = (lines(expected), lines(actual));
+    if expected == actual {
+        return None;
+    }
+
+    // common[i][j] is the length of the longest common subsequence of expected[i..] and actual[j..]
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            edits.push((' ', expected[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1]) {
+            edits.push(('-', expected[i]));
+            i += 1;
+        } else {
+            edits.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..edits.len()).filter(|k| edits[*k].0 != ' ').collect();
+    let mut diff = String::new();
+    let mut skipped = false;
+    for (k, (marker, line)) in edits.iter().enumerate() {
+        if changed.iter().any(|c| c.abs_diff(k) <= DIFF_CONTEXT) {
+            diff.push_str(format!("{} {}", marker, line).trim_end());
+            diff.push('\n');
+            skipped = false;
+        } else if !skipped {
+            diff.push_str("  ...\n");
+            skipped = true;
+        }
+    }
+    Some(diff.trim_end().to_owned())
+}
+
+/// Replace the durations after [`TIME_PREFIX`] by `...`
+fn mask_timings(text: &str) -> String {
+    text.lines()
+        .map(|line| if line.starts_with(TIME_PREFIX) { format!("{}...\n", TIME_PREFIX) } else { format!("{}\n", line) })
+        .collect()
+}
+
+/// Lines without trailing whitespace and trailing empty lines
+fn lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Whether a test file is selected by `filter`, which matches parts of its path
+pub fn matches_filter(file: &Path, filter: Option<&str>) -> bool {
+    filter.is_none_or(|filter| file.to_string_lossy().contains(filter))
+}
+
+/// Print the results in the style of `cargo test`, returns whether all tests passed
+pub fn report(results: &[TestResult], filtered_out: usize, duration: Duration) -> bool {
+    for result in results {
+        let status = if result.error.is_none() { "ok" } else { "FAILED" };
+        println!("test {} ... {} ({:.2?})", result.name, status, result.duration);
+    }
+
+    let failures: Vec<&TestResult> = results.iter().filter(|r| r.error.is_some()).collect();
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for failure in &failures {
+            println!("\n---- {} ----\n{}", failure.name, failure.error.as_ref().unwrap());
+        }
+    }
+
+    let passed = failures.is_empty();
+    println!("\ntest result: {}. {} passed; {} failed; {} filtered out; finished in {:.2?}",
+        if passed { "ok" } else { "FAILED" }, results.len() - failures.len(), failures.len(), filtered_out, duration);
+    passed
+}
@@ -46,3 +46,802 @@ fn issue_1_capture_avoidance() {
         "!reduce (\\x . (\\f . \\x . f (f x)) (\\x' . x (x x')))",
         "\\x . \\x' . (\\x' . x (x x')) ((\\x' . x (x x')) x')");
 }
+
+#[test]
+fn hash_consing_shares_subterms() {
+    use crate::hashcons::TermTable;
+    use crate::lambda::{LambdaNode, LambdaTree};
+    use crate::parsing::Statement;
+    use crate::strategy::{Limits, Strategy};
+
+    let identity = || LambdaTree::new_abstraction("x".to_owned(), LambdaTree::new_variable("x".to_owned()));
+    let mut table = TermTable::new();
+    let term = table.intern(&LambdaTree::new_application(identity(), identity()));
+    let LambdaNode::Application(left, right) = term.node() else { panic!("expected application") };
+    assert!(left.ptr_eq(right));
+    assert_eq!(table.live_terms(), 3);
+
+    // subterms at different positions in the source are shared as well
+    let mut interpreter = Interpreter::new(StdEnvironment::new());
+    let statement = interpreter.interpret_line("(\\x . x) (\\x . x)").unwrap();
+    let Statement::Lambda(term) = statement else { panic!("expected lambda term") };
+    let LambdaNode::Application(left, right) = term.node() else { panic!("expected application") };
+    assert!(left.ptr_eq(right));
+
+    // so are the terms built by reductions
+    let Statement::Lambda(term) = crate::parsing::parse_statement("(\\f . \\x . f x (f x)) g", &Default::default()).unwrap()
+        else { panic!("expected lambda term") };
+    let (term, _) = Strategy::Normal.normalize(term, false, &Limits::default(), interpreter.env()).unwrap();
+    let LambdaNode::Abstraction(_, inner) = term.node() else { panic!("expected abstraction") };
+    let LambdaNode::Application(left, right) = inner.node() else { panic!("expected application") };
+    assert!(left.ptr_eq(right));
+}
+
+#[test]
+fn term_table_drops_unused_terms() {
+    use crate::environment::CaptureEnvironment;
+    use crate::hashcons::TermTable;
+    use crate::lambda::LambdaTree;
+
+    let mut table = TermTable::new();
+    let identity = || LambdaTree::new_abstraction("x".to_owned(), LambdaTree::new_variable("x".to_owned()));
+    let kept = table.intern(&identity());
+    let dropped = table.intern(&LambdaTree::new_application(identity(), identity()));
+    assert_eq!(table.live_terms(), 3);
+    drop(dropped);
+    assert_eq!(table.live_terms(), 2);
+    assert!(table.intern(&identity()).ptr_eq(&kept));
+    table.collect();
+    assert_eq!(table.live_terms(), 2);
+
+    // `!stats` passes its argument on
+    let mut interpreter = Interpreter::new(CaptureEnvironment::new(StdEnvironment::new()));
+    let statement = interpreter.interpret_line("!stats (\\x . x)").unwrap();
+    assert_eq!(statement.to_string(), "\\x . x");
+    assert!(interpreter.env().take_output().starts_with("Interned terms: "));
+}
+
+#[test]
+fn deep_terms_do_not_overflow_the_stack() {
+    use crate::debruijn::DeBruijnNode;
+    use crate::lambda::LambdaTree;
+    use crate::parsing::Statement;
+    use crate::typing;
+
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.set_church_num_enabled(true);
+
+    let statement = interpreter.interpret_line("!normalize (!resolve ((\\y . y) $1000000))").unwrap();
+    let Statement::Lambda(term) = statement else { panic!("expected lambda term") };
+    assert!(term.to_string().starts_with("\\f . \\x . f (f (f"));
+    assert!(typing::infer(term.clone()).is_ok());
+    assert!(DeBruijnNode::from(term.clone()).to_string().starts_with("\\ \\ 2 (2 ("));
+
+    let mut nested = LambdaTree::new_variable("y".to_string());
+    for _ in 0..1_000_000 {
+        nested = LambdaTree::new_abstraction("x".to_string(), nested);
+    }
+    let substituted = nested.substitute("y", LambdaTree::new_variable("x".to_string()));
+    assert!(!nested.alpha_eq(&substituted));
+    assert!(substituted.resolve().alpha_eq(&substituted.set_named_terms(&Default::default())));
+}
+
+#[test]
+fn deep_types_do_not_overflow_the_stack() {
+    use crate::lambda::LambdaTree;
+    use crate::typing;
+
+    // \a f . f a a ... a
+    let var = |name: &str| LambdaTree::new_variable(name.to_owned());
+    let mut body = var("f");
+    for _ in 0..200_000 {
+        body = LambdaTree::new_application(body, var("a"));
+    }
+    let term = LambdaTree::new_abstraction("a".to_owned(), LambdaTree::new_abstraction("f".to_owned(), body));
+    let ty = typing::infer(term).unwrap().to_string();
+    assert!(ty.starts_with("(A -> ((A -> (A -> (A -> "));
+    assert_eq!(ty.matches(" -> ").count(), 200_002);
+}
+
+#[test]
+fn normalization_timeout() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_line("@set timeout 50ms").unwrap();
+    assert!(interpreter.interpret_line("!normalize ((\\x . x x) (\\x . x x))").is_err());
+    assert!(interpreter.interpret_line("@set timeout 5").is_err());
+    let error = interpreter.interpret_line("@set timeout 999999999999999999h").unwrap_err();
+    assert!(error.to_string().starts_with("error[E09]: Set Value Error: invalid value '999999999999999999h'"));
+    interpreter.interpret_line("@set timeout off").unwrap();
+    test_statement(&mut interpreter, "!normalize ((\\x . x) x)", "x");
+}
+
+#[test]
+fn normalization_interrupts() {
+    use crate::environment::Interrupts;
+    use crate::strategy::{Limits, Strategy};
+
+    let interrupts = Interrupts::new();
+    // without a running normalization Ctrl-C is not caught
+    assert!(!interrupts.request());
+    let mut interpreter = Interpreter::new(StdEnvironment::with_interrupts(interrupts.clone()));
+
+    let requests = interrupts.clone();
+    let handle = std::thread::spawn(move || while !requests.request() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    });
+    let error = interpreter.interpret_line("!normalize ((\\x . x x) (\\x . x x))").unwrap_err();
+    handle.join().unwrap();
+    assert!(error.to_string().contains("E05"));
+
+    // the request does not cancel the next normalization
+    assert!(interrupts.requested());
+    let term = interpreter.interpret_line("(\\x . x) y").unwrap();
+    let crate::parsing::Statement::Lambda(term) = term else { panic!("expected lambda term") };
+    let (_, nreductions) = Strategy::Normal.normalize(term, false, &Limits::default(), interpreter.env()).unwrap();
+    assert_eq!(nreductions, 1);
+    assert!(!interrupts.requested());
+}
+
+#[test]
+fn interrupted_compare() {
+    use crate::environment::{CaptureEnvironment, Interrupts};
+
+    // with the `sync` feature the strategies run on worker threads, which see the request as well
+    let interrupts = Interrupts::new();
+    let mut interpreter = Interpreter::new(CaptureEnvironment::new(StdEnvironment::with_interrupts(interrupts.clone())));
+    let handle = std::thread::spawn(move || while !interrupts.request() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    });
+    interpreter.interpret_line("!compare ((\\x . x x) (\\x . x x))").unwrap();
+    handle.join().unwrap();
+    let output = interpreter.env().take_output();
+    assert_eq!(output.matches("Interrupted").count(), 3, "{}", output);
+}
+
+#[test]
+fn normalization_size_limit() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.set_church_num_enabled(true);
+    interpreter.interpret_line("@set maxsize 200").unwrap();
+    let error = interpreter.interpret_line("!normalize (EXP $10 $10)").unwrap_err();
+    assert!(error.to_string().starts_with("error[E10]: Size Error: term grew to"));
+    test_statement(&mut interpreter, "!normalize (ADD $1 $1)", "\\f . \\x . f (f x)");
+    // the size is known after every reduction, so larger limits are checked as often
+    interpreter.interpret_line("@set maxsize 5000").unwrap();
+    let error = interpreter.interpret_line("!normalize (EXP $10 $10)").unwrap_err();
+    assert!(error.to_string().starts_with("error[E10]: Size Error: term grew to 5014 nodes after reduction 469 (maximum is 5000)"));
+}
+
+#[test]
+fn compare_strategies() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    test_statement(&mut interpreter, "!compare ((\\x . \\y . y) ((\\x . x x) (\\x . x)))", "\\y . y");
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn interpreter_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Interpreter<StdEnvironment>>();
+}
+
+#[test]
+fn comments() {
+    use crate::parsing;
+
+    let statements = parsing::parse_statements("#!/usr/bin/env lash
+        -- line comment
+        id := \\x . -- inside a term
+            {- block {- nested -} comment -} x; # trailing
+        id y;
+    ", &Default::default()).unwrap();
+    assert_eq!(statements.len(), 2);
+    assert_eq!(statements[0].to_string(), "id := \\x . x");
+}
+
+#[test]
+fn let_expressions() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    test_statement(&mut interpreter,
+        "let id := \\x . x, k := \\x y . x in k id",
+        "let id := \\x . x in let k := \\x . \\y . x in k id");
+    test_statement(&mut interpreter,
+        "!normalize (let id := \\x . x, k := \\x y . x in k id)",
+        "\\y . \\x . x");
+    // capture avoidance also applies to let binders
+    test_statement(&mut interpreter,
+        "!normalize (let x := y in \\y . x y)",
+        "\\y' . y y'");
+    // keywords are only recognized as whole words
+    test_statement(&mut interpreter, "inlet := letin", "inlet := letin");
+    assert!(interpreter.interpret_line("in := x").is_err());
+}
+
+#[test]
+fn recursive_definitions() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.set_church_num_enabled(true);
+    interpreter.interpret_contents("
+        iszero := \\n . n (\\x . FALSE) TRUE;
+        rec fact := \\n . IFTHENELSE (iszero n) $1 (MULT n (fact (PRED n)));
+        rec even := \\n . IFTHENELSE (iszero n) TRUE (odd (PRED n));
+        and odd := \\n . IFTHENELSE (iszero n) FALSE (even (PRED n));
+    ").unwrap();
+    test_statement(&mut interpreter, "!normalize (fact $3)", "\\f . \\x . f (f (f (f (f (f x)))))");
+    test_statement(&mut interpreter, "!normalize (even $3)", "FALSE");
+    test_statement(&mut interpreter, "!normalize (odd $3)", "TRUE");
+    // the trace shows the names instead of the fixed-point combinator
+    test_statement(&mut interpreter, "!reduce (fact $2)", "IFTHENELSE (iszero $2) $1 (MULT $2 (fact (PRED $2)))");
+    // names of the group shadow earlier definitions
+    test_statement(&mut interpreter, "rec TRUE := \\x . TRUE", "rec TRUE := \\x . TRUE");
+    test_statement(&mut interpreter, "!reduce (TRUE y)", "TRUE");
+}
+
+#[test]
+fn literals() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    test_statement(&mut interpreter, "[a, <b, c>]", "CONS a (CONS (PAIR b c) NIL)");
+    test_statement(&mut interpreter, "!normalize (HEAD (TAIL [a, b]))", "b");
+    test_statement(&mut interpreter, "!normalize (SECOND <a, b>)", "b");
+    test_statement(&mut interpreter, "!normalize (ISNIL \"\")", "TRUE");
+    test_statement(&mut interpreter, "!normalize [a, b]", "\\c . \\n . c a (c b n)");
+
+    let read_back = |interpreter: &mut Interpreter<StdEnvironment>, input: &str| {
+        format!("{:#}", interpreter.interpret_line(input).unwrap())
+    };
+    assert_eq!(read_back(&mut interpreter, "!normalize [a, <b, c>]"), "[a, <b, c>]");
+    assert_eq!(read_back(&mut interpreter, "!normalize (f [a] b)"), "f [a] b");
+    // the empty list is the same term as FALSE
+    assert_eq!(read_back(&mut interpreter, "!normalize (TAIL [a])"), "\\c . \\n . n");
+    // lists of numerals are only printed as strings when asked for
+    assert!(read_back(&mut interpreter, "!normalize \"hi\"").starts_with("[\\f . \\x . f"));
+    let term = interpreter.interpret_line("!normalize [a, \"hi\"]").unwrap();
+    assert_eq!(format!("{:+#}", term), "[a, \"hi\"]");
+    interpreter.interpret_line("@set readback strings").unwrap();
+    assert_eq!(interpreter.format_result(&Ok(term)), "\n==> [a, \"hi\"]\n");
+
+    // literals use the definitions of the standard library, even if they are redefined or not loaded
+    interpreter.interpret_line("@set warnings off").unwrap();
+    interpreter.interpret_line("CONS := x").unwrap();
+    test_statement(&mut interpreter, "!normalize (HEAD [a])", "a");
+    let mut interpreter = Interpreter::new(StdEnvironment::new());
+    test_statement(&mut interpreter, "!normalize ([a, b] (\\h t . h) c)", "a");
+    test_statement(&mut interpreter, "!normalize (<a, b> (\\x y . y))", "b");
+}
+
+#[test]
+fn data_types() {
+    use crate::parsing;
+
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_contents("
+        @data Maybe = Nothing | Just x;
+        @set encoding church;
+        @data List = Nil | Cons x List;
+    ").unwrap();
+    test_statement(&mut interpreter, "!normalize (Just y)", "\\Nothing . \\Just . Just y");
+    test_statement(&mut interpreter,
+        "!normalize (match Just y with Just v -> f v | Nothing -> z)",
+        "f y");
+    test_statement(&mut interpreter, "match m with Nothing -> z | Just v -> v", "matchMaybe m z (\\v . v)");
+    // church encoded values pass the folded tail to the handler
+    test_statement(&mut interpreter,
+        "!normalize (match Cons a (Cons b Nil) with Nil -> n | Cons h t -> c h t)",
+        "c a (c b n)");
+    assert!(interpreter.interpret_line("match m with Nothing -> z").is_err());
+    assert!(interpreter.interpret_line("match m with Nothing -> z | Just -> z").is_err());
+    assert!(interpreter.interpret_line("match m with Nil -> z | Just v -> z").is_err());
+
+    let statement = parsing::parse_statement("match m with Nothing -> z | Just v -> v", &Default::default()).unwrap();
+    assert_eq!(statement.to_string(), "match m with Nothing -> z | Just v -> v");
+}
+
+#[test]
+fn infix_operators() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_contents("
+        @infixl 6 + ADD;
+        @infixl 7 * MULT;
+        @infixr 5 :: CONS;
+        @infix 4 == EQ;
+        sum := a + b * c + d;
+    ").unwrap();
+    // operators declared earlier in the same file are already in effect
+    test_statement(&mut interpreter, "!resolve sum", "ADD (ADD a (MULT b c)) d");
+    test_statement(&mut interpreter, "a + b * c + d", "ADD (ADD a (MULT b c)) d");
+    test_statement(&mut interpreter, "a :: b :: NIL", "CONS a (CONS b NIL)");
+    test_statement(&mut interpreter, "f x * (y + z)", "MULT (f x) (ADD y z)");
+    test_statement(&mut interpreter, "(::) a", "CONS a");
+    assert!(interpreter.interpret_line("a == b == c").is_err());
+    assert!(interpreter.interpret_line("a ++ b").is_err());
+    // operators inside literals
+    test_statement(&mut interpreter, "[a == b, c]", "CONS (EQ a b) (CONS c NIL)");
+    test_statement(&mut interpreter, "<a + b, c :: d>", "PAIR (ADD a b) (CONS c d)");
+    test_statement(&mut interpreter, "<a, b> (x + y)", "PAIR a b (ADD x y)");
+    // `<` and `>` only delimit pairs
+    assert!(interpreter.interpret_line("@infix 4 < LT").is_err());
+    assert!(interpreter.interpret_line("a >= b").is_err());
+}
+
+#[test]
+fn error_positions() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_line("@set warnings off").unwrap();
+    let error = interpreter.interpret_contents("x := y;\n@set foo bar;").unwrap_err();
+    assert!(error.to_string().starts_with("error[E08]: Set Key Error: unknown key 'foo'\n --> 2:1\n"));
+    // errors in terms point at the offending subterm
+    let error = interpreter.interpret_contents("x := y;\n  z := f (!alphaeq x);").unwrap_err();
+    assert_eq!(error.to_string(), "error[E06]: Macro Argument Error: macro alphaeq expects 2 arguments, but 1 were given
+ --> 2:11
+  |
+2 |   z := f (!alphaeq x);
+  |           ^^^^^^^^");
+    let error = interpreter.interpret_line("f (g $2)").unwrap_err();
+    assert!(error.to_string().contains(" --> 1:6\n"));
+    // identical subterms keep their own positions
+    let error = interpreter.interpret_contents("a := !alphaeq x;\nb := y;\nc := !alphaeq x;").unwrap_err();
+    let positions: Vec<_> = error.to_string().lines().filter(|line| line.starts_with(" --> ")).map(str::to_owned).collect();
+    assert_eq!(positions, [" --> 1:6", " --> 3:6"]);
+    let error = interpreter.interpret_contents("a := f (!alphaeq x);\nb := y;\nc := f (!alphaeq x);").unwrap_err();
+    let positions: Vec<_> = error.to_string().lines().filter(|line| line.starts_with(" --> ")).map(str::to_owned).collect();
+    assert_eq!(positions, [" --> 1:9", " --> 3:9"]);
+}
+
+#[test]
+fn error_suggestions() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    let error = interpreter.interpret_line("@set readbak true").unwrap_err();
+    assert!(error.to_string().contains("= help: did you mean `readback`?"));
+    assert!(error.to_string().contains("= help: valid keys are binding, encoding, fuel, maxsize"));
+    let error = interpreter.interpret_line("!normalise x").unwrap_err();
+    assert!(error.to_string().contains("= help: did you mean `!normalize`?"));
+    interpreter.interpret_line("@data Maybe = Nothing | Just x").unwrap();
+    let error = interpreter.interpret_line("match m with Jst v -> v | Nothing -> z").unwrap_err();
+    assert!(error.to_string().contains("= help: did you mean `Just`?"));
+}
+
+#[test]
+fn error_recovery() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    let error = interpreter.interpret_contents("id := \\x . x;
+foo := (\\x . x;
+@set foo bar;
+s := \"a;b\" );
+ok := id y;
+x := y
+").unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("--> 2:15"));
+    assert!(message.contains("Set Key Error: unknown key 'foo'\n --> 3:1"));
+    assert!(message.contains("--> 4:12"));
+    assert!(message.contains("--> 6:7"));
+    // the warning about `y` is reported together with the errors
+    assert!(message.ends_with("4 errors, 1 warning"));
+    // the statements between the errors are still run
+    test_statement(&mut interpreter, "!normalize ok", "y");
+}
+
+#[test]
+fn warnings() {
+    use crate::environment::CaptureEnvironment;
+
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.interpret_line("@set warnings error").unwrap();
+    let error = interpreter.interpret_contents("id := \\x . x;\nnot := \\p . IFTHNELSE p FALSE TRUE;").unwrap_err();
+    assert!(error.to_string().starts_with("error[W01]: Free Variable: 'IFTHNELSE' is not defined\n --> 2:13\n"));
+    assert!(error.to_string().contains("= help: did you mean `IFTHENELSE`?"));
+    // names that only differ in case are not suggested
+    let error = interpreter.interpret_line("f := w").unwrap_err();
+    assert!(!error.to_string().contains("did you mean"));
+    let error = interpreter.interpret_line("!normalize (\\TRUE . TRUE)").unwrap_err();
+    assert!(error.to_string().starts_with("error[W02]: Shadowing: variable 'TRUE' shadows"));
+    let error = interpreter.interpret_line("id := \\x . y").unwrap_err();
+    assert!(error.to_string().starts_with("error[W01]"));
+    let error = interpreter.interpret_line("id := \\x . \\y . x").unwrap_err();
+    assert!(error.to_string().starts_with("error[W03]: Redefinition: 'id' is already defined"));
+    // failed definitions do not change the named terms
+    test_statement(&mut interpreter, "!normalize (id z)", "z");
+    // repeating a definition is fine, and so are the names of a recursive group
+    interpreter.interpret_line("id := \\y . y").unwrap();
+    interpreter.interpret_line("rec f := \\x . f x").unwrap();
+    // the terms generated for data types are checked as well
+    interpreter.interpret_line("@data Maybe = Nothing | Just x").unwrap();
+    interpreter.interpret_line("@data Maybe = Nothing | Just x").unwrap();
+    let error = interpreter.interpret_line("@data Option = None | Just x y").unwrap_err();
+    assert!(error.to_string().starts_with("error[W03]: Redefinition: 'Just' is already defined"));
+    test_statement(&mut interpreter, "!normalize (None)", "None");
+
+    interpreter.interpret_line("@set warnings warn").unwrap();
+    interpreter.interpret_line("id := \\x . y").unwrap();
+    interpreter.interpret_line("@set warnings off").unwrap();
+    interpreter.interpret_line("!normalize (\\TRUE . TRUE)").unwrap();
+
+    // warnings are reported in order with the errors of the same run
+    let mut interpreter = Interpreter::new(CaptureEnvironment::new(StdEnvironment::new()));
+    let error = interpreter.interpret_contents("a := b;\nc := (;\nd := e;").unwrap_err().to_string();
+    let headings: Vec<_> = error.lines().filter(|line| line.starts_with("error[") || line.starts_with("warning[")).collect();
+    assert!(headings[0].starts_with("warning[W01]: Free Variable: 'b'"));
+    assert!(headings[1].starts_with("error[E07]"));
+    assert!(headings[2].starts_with("warning[W01]: Free Variable: 'e'"));
+    assert!(error.ends_with("1 error, 2 warnings"));
+    assert!(interpreter.env().take_output().is_empty());
+    // without errors they are printed once all statements ran
+    interpreter.interpret_contents("f := g;\n!debug f;").unwrap();
+    let output = interpreter.env().take_output();
+    assert!(output.starts_with("f\nwarning[W01]: Free Variable: 'g' is not defined\n --> 1:6"), "{}", output);
+}
+
+#[test]
+fn late_binding() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.set_church_num_enabled(true);
+    interpreter.interpret_contents("
+        iszero := \\n . n (\\x . FALSE) TRUE;
+        early := \\n . iszero n;
+        @set binding late;
+        le := \\m n . iszero (SUB m n);
+        fact := \\n . IFTHENELSE (isz n) $1 (MULT n (fact (PRED n)));
+        isz := \\n . n (\\x . FALSE) TRUE;
+        even := \\n . IFTHENELSE (isz n) TRUE (odd (PRED n));
+        odd := \\n . IFTHENELSE (isz n) FALSE (even (PRED n));
+    ").unwrap();
+    // forward references and cycles are resolved once all names are defined
+    test_statement(&mut interpreter, "!normalize (fact $3)", "\\f . \\x . f (f (f (f (f (f x)))))");
+    test_statement(&mut interpreter, "!normalize (odd $3)", "TRUE");
+    test_statement(&mut interpreter, "!reduce (fact $2)", "IFTHENELSE (isz $2) $1 (MULT $2 (fact (PRED $2)))");
+
+    test_statement(&mut interpreter, "!normalize (le $1 $2)", "TRUE");
+    interpreter.interpret_line("@set warnings error").unwrap();
+    let error = interpreter.interpret_line("iszero := \\n . FALSE").unwrap_err();
+    assert!(error.to_string().contains("= help: the new definition is also used by `le`"));
+    assert!(error.to_string().contains("= help: the previous definition is still used by `early`"));
+    let dependents = interpreter.dependency_graph().dependents(["iszero"]);
+    assert_eq!(dependents.into_iter().collect::<Vec<_>>(), ["early", "le"]);
+
+    interpreter.interpret_line("@set warnings off").unwrap();
+    interpreter.interpret_line("iszero := \\n . FALSE").unwrap();
+    test_statement(&mut interpreter, "!normalize (le $1 $2)", "FALSE");
+    test_statement(&mut interpreter, "!normalize (early $0)", "TRUE");
+}
+
+#[test]
+fn dependency_graph() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_contents("
+        ID := \\x . x;
+        K := \\x y . x;
+        twice := \\f x . f (f x);
+        both := twice ID;
+        rec loop := \\x . loop (K x);
+    ").unwrap();
+    let dependencies = |interpreter: &mut Interpreter<StdEnvironment>, input: &str| {
+        let statement = interpreter.interpret_line(input).unwrap();
+        let crate::parsing::Statement::Lambda(term) = statement else { panic!("expected lambda term") };
+        term.dependencies().into_iter().collect::<Vec<_>>()
+    };
+    assert_eq!(dependencies(&mut interpreter, "both"), ["ID", "twice"]);
+    assert_eq!(dependencies(&mut interpreter, "loop"), ["K", "loop"]);
+    assert_eq!(dependencies(&mut interpreter, "K"), [] as [&str; 0]);
+    assert!(interpreter.interpret_line("@deps bot").unwrap_err().to_string().contains("did you mean `both`?"));
+
+    let graph = interpreter.dependency_graph();
+    assert_eq!(graph.unused().into_iter().collect::<Vec<_>>(), ["both", "loop"]);
+    let dot = graph.to_string();
+    assert!(dot.starts_with("digraph dependencies {\n"));
+    assert!(dot.contains("    \"both\" [style=dashed];\n    \"both\" -> \"ID\";\n    \"both\" -> \"twice\";\n"));
+    assert!(dot.contains("    \"loop\" -> \"loop\";\n"));
+}
+
+#[test]
+fn modules() {
+    use crate::environment::CaptureEnvironment;
+
+    let dir = std::env::temp_dir().join(format!("lash-modules-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/count.lsh"), "count := SUCC count;").unwrap();
+    std::fs::write(dir.join("lib/bools.lsh"), "
+        @include \"count.lsh\";
+        @infixl 3 && AND;
+        isz := \\n . n (\\x . FALSE) TRUE;
+        rec even := \\n . isz n TRUE (odd (PRED n)); and odd := \\n . isz n FALSE (even (PRED n));
+    ").unwrap();
+    std::fs::write(dir.join("cycle.lsh"), "@import \"cycle.lsh\";").unwrap();
+    std::fs::write(dir.join("main.lsh"), "
+        count := \\f x . x;
+        @include \"lib/count.lsh\";
+        @include \"lib/count.lsh\";
+        @import \"lib/bools.lsh\" as B;
+        @import \"lib/bools.lsh\" (even);
+    ").unwrap();
+
+    let env = CaptureEnvironment::new(StdEnvironment::new());
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.set_church_num_enabled(true);
+    interpreter.interpret_file(dir.join("main.lsh")).unwrap();
+    interpreter.env().take_output();
+    // files are included relative to the including file and only once
+    test_statement(&mut interpreter, "!normalize count", "\\f . \\x . f x");
+    test_statement(&mut interpreter, "!normalize (B.odd $3)", "TRUE");
+    test_statement(&mut interpreter, "!normalize (even $3)", "FALSE");
+    assert!(interpreter.interpret_line("odd").is_ok());
+    // the references inside an imported group are qualified too
+    interpreter.interpret_line("!deps B.even").unwrap();
+    assert_eq!(interpreter.env().take_output(), "Dependencies: B.even, B.isz, B.odd, FALSE, PRED, TRUE\n");
+    interpreter.interpret_line("@show B.even").unwrap();
+    assert!(interpreter.env().take_output().starts_with("rec B.even := \\n . B.isz n TRUE (B.odd (PRED n))\n"));
+    assert_eq!(interpreter.dependency_graph().dependencies("B.even").unwrap().iter().collect::<Vec<_>>(), ["B.isz", "B.odd", "PRED", "TRUE"]);
+    // operators declared by an imported file stay local to it
+    assert!(interpreter.interpret_line("TRUE && FALSE").is_err());
+
+    let error = interpreter.interpret_line(&format!("@import \"{}\" (od)", dir.join("lib/bools.lsh").display())).unwrap_err();
+    assert!(error.to_string().contains("did you mean `odd`?"));
+    let error = interpreter.interpret_line(&format!("@import \"{}\"", dir.join("cycle.lsh").display())).unwrap_err();
+    assert!(error.to_string().contains("error[E16]"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn scopes() {
+    use crate::strategy::Strategy;
+    let dir = std::env::temp_dir().join(format!("lash-scopes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("settings.lsh"), "
+        @set strategy applicative;
+        @set numerals true;
+        TWO := \\f x . f (f x);
+    ").unwrap();
+    std::fs::write(dir.join("unclosed.lsh"), "@begin; HIDDEN := \\x . x;").unwrap();
+
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    // settings of an included file end with it, its definitions are kept
+    interpreter.interpret_line(&format!("@include \"{}\"", dir.join("settings.lsh").display())).unwrap();
+    assert_eq!(interpreter.strategy(), Strategy::Normal);
+    test_statement(&mut interpreter, "!normalize (SUCC TWO)", "\\f . \\x . f (f (f x))");
+    assert!(interpreter.interpret_line("$1").is_err());
+
+    interpreter.interpret_contents("
+        ID := \\x . x;
+        @begin;
+        @set numerals true;
+        ID := \\x . $1;
+        LOCAL := ID a;
+        @end;
+    ").unwrap();
+    test_statement(&mut interpreter, "!normalize (ID a)", "a");
+    test_statement(&mut interpreter, "!normalize LOCAL", "LOCAL");
+    assert!(interpreter.interpret_line("$1").is_err());
+
+    let error = interpreter.interpret_line(&format!("@include \"{}\"", dir.join("unclosed.lsh").display())).unwrap_err();
+    assert!(error.to_string().contains("error[E17]: Scope Error: `@begin` without `@end`"));
+    test_statement(&mut interpreter, "!normalize HIDDEN", "HIDDEN");
+    let error = interpreter.interpret_line("@end").unwrap_err();
+    assert!(error.to_string().contains("`@end` without `@begin`"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn removing_definitions() {
+    use crate::environment::CaptureEnvironment;
+
+    let env = CaptureEnvironment::new(StdEnvironment::new());
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.interpret_contents("
+        @set binding late;
+        apply := \\x . helper x;
+        helper := \\x . x;
+        rec loop := \\x . loop x;
+    ").unwrap();
+    interpreter.interpret_line("@env").unwrap();
+    let listing = interpreter.env().take_output();
+    assert!(listing.contains("\napply := \\x . helper x\n"));
+    assert!(listing.contains("\nloop := \\x . loop x\n"));
+    assert!(listing.contains("\nTRUE := \\x . \\y . x\n"));
+    interpreter.interpret_line("@env *oo?").unwrap();
+    assert_eq!(interpreter.env().take_output(), "loop := \\x . loop x\n");
+    interpreter.interpret_line("@env ?RU*").unwrap();
+    assert_eq!(interpreter.env().take_output(), "TRUE := \\x . \\y . x\n");
+    interpreter.interpret_line("@show apply").unwrap();
+    let output = interpreter.env().take_output();
+    let ty = output.strip_prefix("apply := \\x . helper x\nType: (").unwrap().strip_suffix(")\n").unwrap();
+    let (argument, result) = ty.split_once(" -> ").unwrap();
+    assert_eq!(argument, result);
+    interpreter.interpret_line("@show loop").unwrap();
+    assert_eq!(interpreter.env().take_output(),
+        "loop := \\x . loop x\nCannot infer type: not inferable for recursive definitions like 'loop'\n");
+    assert!(interpreter.interpret_line("@show aply").unwrap_err().to_string().contains("did you mean `apply`?"));
+
+    test_statement(&mut interpreter, "!normalize (apply a)", "a");
+    interpreter.interpret_line("@undef helper").unwrap();
+    // late bound terms no longer find the removed definition
+    test_statement(&mut interpreter, "!normalize (apply a)", "helper a");
+    test_statement(&mut interpreter, "helper", "helper");
+    assert!(interpreter.dependency_graph().dependencies("helper").is_none());
+    assert!(interpreter.interpret_line("@undef helper").unwrap_err().to_string().contains("error[E15]"));
+}
+
+#[test]
+fn settings() {
+    use crate::settings::Value;
+    use core::time::Duration;
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_contents("
+        @set maxsize 100000;
+        @set timeout 1500ms;
+        @set prompt \"λ> \";
+    ").unwrap();
+    assert_eq!(interpreter.get("maxsize").unwrap(), Value::Integer(100000));
+    assert_eq!(interpreter.get("timeout").unwrap(), Value::Duration(Duration::from_millis(1500)));
+    assert_eq!(interpreter.get("prompt").unwrap().to_string(), "\"λ> \"");
+    assert_eq!(interpreter.get("strategy").unwrap().to_string(), "normal");
+    test_statement(&mut interpreter, "@set prompt \"λ> \"", "@set prompt \"λ> \"");
+    assert!(interpreter.interpret_line("@get timeout").is_ok());
+    assert!(interpreter.interpret_line("@settings").is_ok());
+
+    interpreter.interpret_line("@set timeout off").unwrap();
+    assert_eq!(interpreter.get("timeout").unwrap(), Value::Off);
+    let error = interpreter.interpret_line("@set numerals maybe").unwrap_err();
+    assert!(error.to_string().contains("invalid value 'maybe' for 'numerals'"));
+    assert!(error.to_string().contains("= help: `numerals` expects true|false"));
+    assert!(interpreter.interpret_line("@set readback off").is_err());
+    assert!(interpreter.interpret_line("@get strategi").unwrap_err().to_string().contains("did you mean `strategy`?"));
+}
+
+#[test]
+fn assertions() {
+    let env = StdEnvironment::new();
+    let mut interpreter = Interpreter::new(env);
+    interpreter.interpret_std().unwrap();
+    interpreter.set_church_num_enabled(true);
+    interpreter.interpret_contents("
+        @assert ADD $1 $1 == $2;
+        @assert_alpha (\\x . x) == ID;
+        @assert_type K : a -> b -> a;
+        @assert_diverges OMEGA OMEGA;
+        @infixl 6 + ADD;
+        @assert $1 + $1 == $2;
+    ").unwrap();
+
+    let error = interpreter.interpret_contents("
+        @assert ADD $1 $1 == $3;
+        @assert_type K : a -> b -> b;
+        @assert_diverges K a;
+    ").unwrap_err().to_string();
+    assert!(error.contains("error[E19]: Assertion Failed: `ADD $1 $1` is not equal to `$3`\n --> 2:9"));
+    assert!(error.contains("`K` has type `(A -> (B -> A))`, not `(a -> (b -> b))`"));
+    assert!(error.contains("`K a` has the normal form `\\y . a`"));
+    assert!(error.contains("3 errors"));
+
+    interpreter.interpret_line("@set fuel 100").unwrap();
+    let error = interpreter.interpret_line("@assert OMEGA OMEGA == OMEGA").unwrap_err();
+    assert!(error.to_string().contains("has no normal form within 100 reductions"));
+}
+
+#[test]
+fn test_files() {
+    use crate::testing;
+    let dir = std::env::temp_dir().join(format!("lash-tests-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("passing.lsh"), "@assert ID a == a;").unwrap();
+    std::fs::write(dir.join("nested/failing.lsh"), "@assert ID a == b; @assert K a b == b;").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a test").unwrap();
+
+    let files = testing::discover(std::slice::from_ref(&dir)).unwrap();
+    assert_eq!(files, [dir.join("nested/failing.lsh"), dir.join("passing.lsh")]);
+    assert!(testing::matches_filter(&files[1], Some("pass")));
+    assert!(!testing::matches_filter(&files[0], Some("pass")));
+
+    let results = testing::run(&files, || {
+        let mut interpreter = Interpreter::new(crate::environment::CaptureEnvironment::new(StdEnvironment::new()));
+        interpreter.interpret_std().unwrap();
+        interpreter
+    });
+    assert!(results[1].error.is_none());
+    // each file runs in a fresh interpreter and reports all of its failures
+    assert!(results[0].error.as_ref().unwrap().to_string().contains("2 errors"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn golden_output() {
+    use crate::environment::CaptureEnvironment;
+    use crate::testing;
+    assert_eq!(testing::diff("a\nb\n", "a\nb  \n\n"), None);
+    assert_eq!(testing::diff("a\nb\nc\n", "a\nx\nc\n").unwrap(), "  a\n- b\n+ x\n  c");
+    // timings are not compared
+    assert!(testing::check_output("a\nTime elapsed: 3ms\n", "a\nTime elapsed: 1s 20ms\n").is_ok());
+    assert!(testing::check_output("a\nTime elapsed: 3ms\n", "b\nTime elapsed: 3ms\n").is_err());
+    assert_eq!(testing::embedded_output("ID;\n{- output:\nfirst\n-}\nID;\n  {- output:\nsecond\n  -}\n").unwrap(),
+        "first\nsecond\n");
+    assert_eq!(testing::embedded_output("{- comment -}"), None);
+
+    let dir = std::env::temp_dir().join(format!("lash-golden-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("matching.lsh"), "@echo \"Hello\";\n!debug (!vnormalize (ID a));\n{- output:\nHello\nID a\na\n-}\n").unwrap();
+    std::fs::write(dir.join("differing.lsh"), "@echo \"Hello\";\n{- output:\nHallo\n-}\n").unwrap();
+    std::fs::write(dir.join("failing.lsh"), "x := y;\n@undef z;\n{- output:\n-}\n").unwrap();
+
+    let new_interpreter = || {
+        let mut interpreter = Interpreter::new(CaptureEnvironment::new(StdEnvironment::new()));
+        interpreter.interpret_std().unwrap();
+        interpreter
+    };
+    // warnings and errors are part of the output, the path of the script is replaced by its name
+    let output = testing::capture(&mut new_interpreter(), &dir.join("failing.lsh"));
+    assert!(output.starts_with("warning[W01]"));
+    assert!(output.contains("--> failing.lsh:2:1") && output.contains("error[E15]"));
+    assert!(!output.contains(&*dir.to_string_lossy()));
+
+    let files = testing::discover(std::slice::from_ref(&dir)).unwrap();
+    let results = testing::run(&files, new_interpreter);
+    assert!(results[0].error.as_ref().unwrap().to_string().contains("- Hallo\n+ Hello"));
+    assert!(results[1].error.as_ref().unwrap().to_string().contains("E20"));
+    assert!(results[2].error.is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn doctests() {
+    use crate::doctest;
+    use crate::environment::CaptureEnvironment;
+    let markdown = "Text\n```\n[λ] x := a\n\n==> x := a\n\n[λ] !vnormalize (ID a)\nID a\n\n==> a\n```\n\n\
+        ```sh\nlash doctest\n```\n```\n[λ] ID\nID\n```\n";
+    let transcripts = doctest::extract(markdown, "[λ] ");
+    assert_eq!(transcripts.len(), 2);
+    assert_eq!(transcripts[0].line, 2);
+    assert_eq!(transcripts[0].inputs, ["x := a", "!vnormalize (ID a)"]);
+
+    let new_interpreter = || {
+        let mut interpreter = Interpreter::new(CaptureEnvironment::new(StdEnvironment::new()));
+        interpreter.interpret_std().unwrap();
+        interpreter
+    };
+    // printed output and warnings come before the result
+    let session = doctest::replay(&mut new_interpreter(), &transcripts[0].inputs);
+    assert!(session.starts_with("[λ] x := a\nwarning[W01]"));
+    assert!(session.ends_with("[λ] !vnormalize (ID a)\nID a\n\n==> a\n\n"));
+
+    let dir = std::env::temp_dir().join(format!("lash-doctests-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("doc.md"), markdown.replace("[λ] x := a", "[λ] @set warnings off\n\n==> @set warnings off\n\n[λ] x := a")).unwrap();
+    let results = doctest::run(&dir.join("doc.md"), new_interpreter).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].name.ends_with("doc.md:2"));
+    assert!(results[0].error.is_none());
+    assert!(results[1].error.as_ref().unwrap().to_string().contains("- ID\n+\n+ ==> ID"));
+
+    // the input lines start with the prompt that is set
+    std::fs::write(dir.join("doc.md"), "```\n> ID\n\n==> ID\n```\n").unwrap();
+    let results = doctest::run(&dir.join("doc.md"), || {
+        let mut interpreter = new_interpreter();
+        interpreter.interpret_line("@set prompt \"> \"").unwrap();
+        interpreter
+    }).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].error.is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1,31 +1,48 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::format;
 use alloc::rc::Rc;
 use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 use core::cell::RefCell;
 use core::cmp;
 use core::fmt::Display;
+use core::mem;
 
 use crate::lambda::{LambdaNode, LambdaTree};
 
 type VarName = String;
 type Level = usize;
-type Environment = BTreeMap<VarName, Type>;
+/// Types of the variables in scope, innermost binding last
+type Environment = BTreeMap<VarName, Vec<Type>>;
 
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(Debug)]
 pub enum TypeVariable {
     Unbound(String, Level),
-    Link(Box<Type>),
+    Link(Type),
+}
+
+/// A type, whose type variables are shared by all types that contain them
+///
+/// Clones are cheap, and dropping a deeply nested type does not recurse.
+#[derive(Clone, Debug)]
+pub struct Type(Rc<TypeNode>);
+
+#[derive(Debug)]
+enum TypeNode {
+    Var(RefCell<TypeVariable>),
+    Arrow(Type, Type),
 }
 
+/// A type as it is written down, with named type variables
 #[derive(PartialEq, Clone, Debug)]
-pub enum Type {
-    TypeVar(Rc<RefCell<TypeVariable>>),
-    TypeArrow(Box<Type>, Box<Type>),
+pub enum TypeExpr {
+    Var(String),
+    Arrow(Box<TypeExpr>, Box<TypeExpr>),
 }
 
 struct TypeMachine {
@@ -53,139 +70,275 @@ impl TypeMachine {
     }
 
     fn newvar(&mut self) -> Type {
-        use Type::*;
-        use TypeVariable::*;
-        TypeVar(Rc::new(RefCell::new(Unbound(self.gensym(), self.current_level))))
+        Type::var(TypeVariable::Unbound(self.gensym(), self.current_level))
     }
 
-    fn occurs(tv: &Rc<RefCell<TypeVariable>>, t: &Type) -> Result<(), String> {
-        use Type::*;
+    /// Fail if the type variable `tv` occurs in `t`, and lower the levels of the variables in `t` to the one of `tv`
+    ///
+    /// Parts of `t` that are shared are only visited once.
+    fn occurs(tv: &Type, t: &Type) -> Result<(), String> {
         use TypeVariable::*;
-        match t {
-            TypeVar(tv2) => if tv == tv2 {
-                Err(format!("occurs check failed - {} == {}", tv.borrow(), tv2.borrow()))
-            } else if let Unbound(name, level) = tv2.borrow().clone() {
-                let min_level = match &*tv.borrow() {
-                    Unbound(_, l) => cmp::min(level, *l),
-                    _ => level,
-                };
-                // *tv2.borrow_mut() = Unbound(name.to_owned(), min_level);
-                unsafe { *tv2.as_ptr() = Unbound(name.to_string(), min_level); }
-                Ok(())
-            } else if let Link(ty) = &*tv2.borrow() {
-                Self::occurs(tv, ty)
-            } else {
-                Err(format!("occurs check failed - {} versus {}", tv.borrow(), tv2.borrow()))
-            },
-            TypeArrow(t1, t2) => { Self::occurs(tv, t1)?;Self::occurs(tv, t2) },
+        let TypeNode::Var(var) = &*tv.0 else { unreachable!() };
+        let Unbound(_, level) = *var.borrow() else { unreachable!() };
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![t.clone()];
+        while let Some(t) = pending.pop() {
+            let t = Self::prune(&t);
+            match &*t.0 {
+                TypeNode::Var(_) if t.ptr_eq(tv) => {
+                    return Err(format!("occurs check failed - {} == {}", tv, t));
+                },
+                TypeNode::Var(var) => if let Unbound(_, l) = &mut *var.borrow_mut() {
+                    *l = cmp::min(*l, level);
+                },
+                TypeNode::Arrow(t1, t2) => if visited.insert(t.addr()) {
+                    pending.push(t2.clone());
+                    pending.push(t1.clone());
+                },
+            }
         }
+        Ok(())
     }
 
-    fn unify(t1: &Type, t2: &Type) -> Result<(), String> {
-        use Type::*;
+    /// Follow the links of a type variable to the type it stands for
+    ///
+    /// All variables on the way are linked directly to the result, so repeated lookups stay cheap
+    /// and long chains of links cannot build up.
+    fn prune(t: &Type) -> Type {
         use TypeVariable::*;
-
-        let is_unbound = |tv: &RefCell<_>| matches!(*tv.borrow(), Unbound(..));
-
-        if t1 == t2 { return Ok(()); }
-
-        if let TypeVar(tv) = t1 {
-            if let Link(tv) = &*tv.borrow() {
-                return Self::unify(&tv, t2);
+        let mut path = Vec::new();
+        let mut current = t.clone();
+        loop {
+            let next = match &*current.0 {
+                TypeNode::Var(var) => match &*var.borrow() {
+                    Link(ty) => Some(ty.clone()),
+                    Unbound(..) => None,
+                },
+                TypeNode::Arrow(..) => None,
+            };
+            match next {
+                Some(next) => path.push(mem::replace(&mut current, next)),
+                None => break,
             }
         }
 
-        if let TypeVar(tv) = t2 {
-            if let Link(tv) = &*tv.borrow() {
-                return Self::unify(t1, &tv);
+        // the last variable on the way already links to the result
+        path.pop();
+        for ty in path.iter().rev() {
+            if let TypeNode::Var(var) = &*ty.0 {
+                *var.borrow_mut() = Link(current.clone());
             }
         }
+        current
+    }
 
+    fn unify(t1: &Type, t2: &Type) -> Result<(), String> {
+        use TypeVariable::*;
 
-        if let TypeVar(tv) = t1 {
-            if is_unbound(tv) {
-                Self::occurs(tv, t2)?;
-                *tv.borrow_mut() = Link(Box::new(t2.clone()));
-                return Ok(());
+        let mut pending = vec![(t1.clone(), t2.clone())];
+        while let Some((t1, t2)) = pending.pop() {
+            let t1 = Self::prune(&t1);
+            let t2 = Self::prune(&t2);
+            if t1.ptr_eq(&t2) {
+                continue;
+            }
+            match (&*t1.0, &*t2.0) {
+                (TypeNode::Var(var), _) => {
+                    Self::occurs(&t1, &t2)?;
+                    *var.borrow_mut() = Link(t2.clone());
+                },
+                (_, TypeNode::Var(var)) => {
+                    Self::occurs(&t2, &t1)?;
+                    *var.borrow_mut() = Link(t1.clone());
+                },
+                (TypeNode::Arrow(tyl1, tyl2), TypeNode::Arrow(tyr1, tyr2)) => {
+                    pending.push((tyl2.clone(), tyr2.clone()));
+                    pending.push((tyl1.clone(), tyr1.clone()));
+                },
             }
         }
+        Ok(())
+    }
 
-        if let TypeVar(tv) = t2 {
-            if is_unbound(tv) {
-                Self::occurs(tv, t1)?;
-                *tv.borrow_mut() = Link(Box::new(t1.clone()));
-                return Ok(());
-            }
+    fn infer(&mut self, env: &mut Environment, lambda: LambdaTree) -> Result<Type, String> {
+        use LambdaNode::*;
+
+        enum Work {
+            Visit(LambdaTree),
+            Abstraction(VarName, Type),
+            Application,
         }
 
-        if let TypeArrow(tyl1, tyl2) = t1 {
-            if let TypeArrow(tyr1, tyr2) = t2 {
-                Self::unify(tyl1, tyr1)?;
-                return Self::unify(tyl2, tyr2);
+        let mut work = vec![Work::Visit(lambda)];
+        let mut results = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(lambda) => match lambda.node() {
+                    Variable(x) => {
+                        // without generalization a variable has the type of its binding
+                        let ty = env.get(x).and_then(|tys| tys.last()).ok_or(format!("unable to instantiate {}", x))?;
+                        results.push(ty.clone());
+                    },
+                    Abstraction(x, e) => {
+                        let ty_x = self.newvar();
+                        env.entry(x.to_string()).or_default().push(ty_x.clone());
+                        work.push(Work::Abstraction(x.to_string(), ty_x));
+                        work.push(Work::Visit(e.clone()));
+                    },
+                    Application(e1, e2) => {
+                        work.push(Work::Application);
+                        work.push(Work::Visit(e2.clone()));
+                        work.push(Work::Visit(e1.clone()));
+                    },
+                    // typed like the redex (\x . e2) e1
+                    Let(x, e1, e2) => {
+                        let ty_x = self.newvar();
+                        env.entry(x.to_string()).or_default().push(ty_x.clone());
+                        work.push(Work::Application);
+                        work.push(Work::Visit(e1.clone()));
+                        work.push(Work::Abstraction(x.to_string(), ty_x));
+                        work.push(Work::Visit(e2.clone()));
+                    },
+                    // the fixed-point combinator of a recursive definition has no simple type
+                    Named(n) if n.body().is_some() => {
+                        return Err(format!("not inferable for recursive definitions like '{}'", n.name()));
+                    },
+                    Named(n) => work.push(Work::Visit(n.term())),
+                    ChurchNum(n) => work.push(Work::Visit(LambdaTree::unwrap_church_num(*n))),
+                    Macro(_, _) | Match(_, _) => unreachable!(),
+                },
+                Work::Abstraction(x, ty_x) => {
+                    if let Some(tys) = env.get_mut(&x) {
+                        tys.pop();
+                        if tys.is_empty() {
+                            env.remove(&x);
+                        }
+                    }
+                    let ty_e = results.pop().unwrap();
+                    results.push(Type::arrow(ty_x, ty_e));
+                },
+                Work::Application => {
+                    let ty_arg = results.pop().unwrap();
+                    let ty_fun = results.pop().unwrap();
+                    let ty_res = self.newvar();
+                    Self::unify(&ty_fun, &Type::arrow(ty_arg, ty_res.clone()))?;
+                    results.push(ty_res);
+                },
             }
         }
+        Ok(results.pop().unwrap())
+    }
+}
+
+pub fn infer(lambda: LambdaTree) -> Result<Type, String> {
+    TypeMachine::new().infer(&mut Environment::new(), lambda)
+}
+
+impl Type {
+    fn var(var: TypeVariable) -> Self {
+        Type(Rc::new(TypeNode::Var(RefCell::new(var))))
+    }
+
+    fn arrow(argument: Type, result: Type) -> Self {
+        Type(Rc::new(TypeNode::Arrow(argument, result)))
+    }
 
-        Err(format!("unable to unify {} with {}", t1, t2))
+    fn ptr_eq(&self, other: &Type) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
 
-    fn inst(&mut self, t: Type) -> Type {
-        Self::inst_rec(t, BTreeMap::new()).0
+    fn addr(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
     }
 
-    fn inst_rec(t: Type, subst: BTreeMap<String, Type>) -> (Type, BTreeMap<String, Type>) {
-        use Type::*;
+    /// Whether this type is `expected` with the type variables renamed
+    pub fn matches(&self, expected: &TypeExpr) -> bool {
         use TypeVariable::*;
+        // both directions of the renaming, so distinct variables stay distinct
+        let mut renaming: BTreeMap<String, String> = BTreeMap::new();
+        let mut reverse: BTreeMap<String, String> = BTreeMap::new();
+        let mut pending = vec![(self.clone(), expected)];
+        while let Some((ty, expected)) = pending.pop() {
+            let ty = TypeMachine::prune(&ty);
+            match (&*ty.0, expected) {
+                (TypeNode::Var(var), TypeExpr::Var(name)) => {
+                    let Unbound(var, _) = &*var.borrow() else { unreachable!() };
+                    let forward = renaming.entry(var.clone()).or_insert_with(|| name.clone());
+                    let backward = reverse.entry(name.clone()).or_insert_with(|| var.clone());
+                    if forward != name || backward != var {
+                        return false;
+                    }
+                },
+                (TypeNode::Arrow(t1, t2), TypeExpr::Arrow(e1, e2)) => {
+                    pending.push((t2.clone(), e2));
+                    pending.push((t1.clone(), e1));
+                },
+                _ => return false,
+            }
+        }
+        true
+    }
 
-        match t {
-            TypeVar(ref tv) => match &*tv.borrow() {
-                Link(ty) => Self::inst_rec(*ty.clone(), subst),
-                Unbound(..) => (t.clone(), subst),
+    /// Move the parts out of this type if it is not shared, so they can be dropped one by one
+    fn take_children(&mut self, stack: &mut Vec<Type>) {
+        let Some(node) = Rc::get_mut(&mut self.0) else { return };
+        let leaf = TypeNode::Var(RefCell::new(TypeVariable::Unbound(String::new(), 0)));
+        match mem::replace(node, leaf) {
+            TypeNode::Arrow(t1, t2) => {
+                stack.push(t1);
+                stack.push(t2);
             },
-            TypeArrow(ty1, ty2) => {
-                let (ty1, subst) = Self::inst_rec(*ty1, subst);
-                let (ty2, subst) = Self::inst_rec(*ty2, subst);
-                (TypeArrow(Box::new(ty1), Box::new(ty2)), subst)
+            TypeNode::Var(var) => if let TypeVariable::Link(ty) = var.into_inner() {
+                stack.push(ty);
             },
         }
     }
+}
 
-    fn infer(&mut self, env: &Environment, lambda: LambdaTree) -> Result<Type, String> {
-        use LambdaNode::*;
-        use Type::*;
-        match lambda.node() {
-            Variable(x) => Ok(self.inst(env.get(x).ok_or(format!("unable to instantiate {}", x))?.clone())),
-            Abstraction(x, e) => {
-                let ty_x = self.newvar();
-                let mut nextenv = env.clone();
-                nextenv.insert(x.to_string(), ty_x.clone());
-                let ty_e = self.infer(&nextenv, e.clone())?;
-                Ok(TypeArrow(Box::new(ty_x), Box::new(ty_e)))
-            },
-            Application(e1, e2) => {
-                let ty_fun = self.infer(env, e1.clone())?;
-                let ty_arg = Box::new(self.infer(env, e2.clone())?);
-                let ty_res = Box::new(self.newvar());
-                Self::unify(&ty_fun, &TypeArrow(ty_arg, ty_res.clone()))?;
-                Ok(*ty_res)
-            },
-            Named(n) => self.infer(env, n.term()),
-            ChurchNum(n) => self.infer(env, LambdaTree::unwrap_church_num(*n)),
-            Macro(_, _) => unreachable!(),
+impl Drop for Type {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+        while let Some(mut ty) = stack.pop() {
+            ty.take_children(&mut stack);
         }
     }
 }
 
-pub fn infer(lambda: LambdaTree) -> Result<Type, String> {
-    TypeMachine::new().infer(&Environment::new(), lambda)
+impl Display for TypeExpr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TypeExpr::Var(name) => write!(f, "{}", name),
+            TypeExpr::Arrow(ty1, ty2) => write!(f, "({} -> {})", ty1, ty2),
+        }
+    }
 }
 
 impl Display for Type {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        use Type::*;
-        match self {
-            TypeVar(var) => write!(f, "{}", var.borrow()),
-            TypeArrow(ty1, ty2) => write!(f, "({} -> {})", ty1, ty2),
+        enum Piece {
+            Type(Type),
+            Text(&'static str),
+        }
+
+        let mut pieces = vec![Piece::Type(self.clone())];
+        while let Some(piece) = pieces.pop() {
+            let ty = match piece {
+                Piece::Type(ty) => TypeMachine::prune(&ty),
+                Piece::Text(text) => { f.write_str(text)?; continue },
+            };
+            match &*ty.0 {
+                TypeNode::Var(var) => write!(f, "{}", var.borrow())?,
+                TypeNode::Arrow(ty1, ty2) => {
+                    pieces.push(Piece::Text(")"));
+                    pieces.push(Piece::Type(ty2.clone()));
+                    pieces.push(Piece::Text(" -> "));
+                    pieces.push(Piece::Type(ty1.clone()));
+                    pieces.push(Piece::Text("("));
+                },
+            }
         }
+        Ok(())
     }
 }
 